@@ -0,0 +1,146 @@
+use crate::auth::Identity;
+use crate::config::{Config, ConfigAudit};
+use axum::extract::{ConnectInfo, Query};
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use log::error;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+
+/// Audit log of queries, if configured
+static AUDIT_LOG: Lazy<Mutex<Option<AuditLog>>> =
+    Lazy::new(|| Mutex::new(Config::global().audit.as_ref().map(AuditLog::new)));
+
+/// Amount of results of a query, set by handlers as a response extension for the audit log
+#[derive(Debug, Clone, Copy)]
+pub struct AuditResults(pub usize);
+
+#[derive(Debug, Serialize)]
+struct AuditRecord<'a> {
+    timestamp: DateTime<Utc>,
+    client_ip: Option<IpAddr>,
+    identity: &'a str,
+    endpoint: &'a str,
+    filters: BTreeMap<String, String>,
+    status: u16,
+    /// Not set for streams and rejected queries
+    results: Option<usize>,
+}
+
+/// JSON lines file that is rotated to `<file>.1` ... `<file>.<keep>` when it exceeds its max size
+struct AuditLog {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize,
+    file: Option<File>,
+    size: u64,
+}
+
+impl AuditLog {
+    fn new(config: &ConfigAudit) -> Self {
+        AuditLog {
+            path: PathBuf::from(&config.file),
+            max_size: config.max_size_mb * 1024 * 1024,
+            keep: config.keep,
+            file: None,
+            size: 0,
+        }
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{n}"));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+        if self.keep == 0 {
+            return fs::remove_file(&self.path);
+        }
+        for n in (1..self.keep).rev() {
+            let from = self.rotated(n);
+            if from.exists() {
+                fs::rename(from, self.rotated(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated(1))
+    }
+
+    fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.file.is_some() && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?;
+                self.size = file.metadata()?.len();
+                self.file.insert(file)
+            }
+        };
+        file.write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// Switches to the audit log of the current config, the file is reopened on the next query
+pub fn reload() {
+    *AUDIT_LOG.lock() = Config::global().audit.as_ref().map(AuditLog::new);
+}
+
+/// Records who queried which endpoint with which filters and how many results they got.
+/// Has to run after authentication, so the identity is known.
+pub async fn audit<B>(req: Request<B>, next: Next<B>) -> Response {
+    if AUDIT_LOG.lock().is_none() {
+        return next.run(req).await;
+    }
+    let timestamp = Utc::now();
+    let client_ip = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let identity = req
+        .extensions()
+        .get::<Identity>()
+        .map_or_else(|| String::from("unknown"), |i| i.name.clone());
+    let endpoint = req.uri().path().to_string();
+    let filters = Query::<BTreeMap<String, String>>::try_from_uri(req.uri())
+        .map(|Query(filters)| filters)
+        .unwrap_or_default();
+    let response = next.run(req).await;
+    let record = AuditRecord {
+        timestamp,
+        client_ip,
+        identity: &identity,
+        endpoint: &endpoint,
+        filters,
+        status: response.status().as_u16(),
+        results: response.extensions().get::<AuditResults>().map(|r| r.0),
+    };
+    let written = serde_json::to_vec(&record)
+        .map_err(io::Error::from)
+        .and_then(|mut line| {
+            line.push(b'\n');
+            match AUDIT_LOG.lock().as_mut() {
+                Some(audit_log) => audit_log.write(&line),
+                None => Ok(()),
+            }
+        });
+    if let Err(why) = written {
+        error!("Failed to write audit log: {why}");
+    }
+    response
+}
@@ -0,0 +1,188 @@
+use crate::config::Config;
+use crate::error::{ApiError, ErrorCode};
+use crate::mail::Mail;
+use crate::redact::Redactor;
+use crate::tls::ClientCert;
+use axum::http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use log::warn;
+
+/// Who is querying the API, inserted into every authenticated request
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub name: String,
+    /// Recipient or sender domains this identity may see, all if None
+    pub domains: Option<Vec<String>>,
+    /// What is redacted from mails before they are returned to this identity
+    pub redactor: Option<Redactor>,
+    /// May use the admin endpoints
+    pub admin: bool,
+}
+
+impl Identity {
+    /// Identity of every request when no auth is configured
+    pub fn anonymous() -> Self {
+        Identity {
+            name: String::from("anonymous"),
+            domains: None,
+            redactor: None,
+            admin: false,
+        }
+    }
+
+    /// Domains are lowercased and a leading @ is ignored, an empty list means all domains.
+    /// The redaction profile has been validated to exist when reading the config.
+    fn new(name: &str, domains: &[String], redaction: Option<&String>, admin: bool) -> Self {
+        let redactor = redaction.and_then(|profile| Config::global().redactor(profile));
+        Identity {
+            name: name.to_string(),
+            domains: (!domains.is_empty()).then(|| {
+                domains
+                    .iter()
+                    .map(|d| d.trim_start_matches('@').to_lowercase())
+                    .collect()
+            }),
+            redactor,
+            admin,
+        }
+    }
+
+    pub fn redact(&self, mail: Mail) -> Mail {
+        match &self.redactor {
+            Some(redactor) => redactor.redact(mail),
+            None => mail,
+        }
+    }
+
+    pub fn is_unrestricted(&self) -> bool {
+        self.domains.is_none()
+    }
+}
+
+/// Whether the domain of the address is one of the given domains or a subdomain of them
+pub fn in_domains(address: &str, domains: &[String]) -> bool {
+    let Some((_, domain)) = address.rsplit_once('@') else {
+        return false;
+    };
+    let domain = domain.to_lowercase();
+    domains.iter().any(|d| {
+        domain == *d
+            || domain
+                .strip_suffix(d.as_str())
+                .is_some_and(|sub| sub.ends_with('.'))
+    })
+}
+
+/// Compares in constant time, so tokens can't be guessed by timing the comparison
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn unauthorized(message: &str) -> Response {
+    (
+        [(WWW_AUTHENTICATE, "Bearer")],
+        ApiError::new(ErrorCode::Unauthorized, message),
+    )
+        .into_response()
+}
+
+/// Maps a verified client certificate to the configured client with a matching subject.
+/// Unmatched certificates are only trusted with full access without `auth`, where the API is open anyway,
+/// or with `auth.allow_unmapped_clients`, so they can't bypass the scopes of tokens and clients.
+fn client_identity(cert: &ClientCert) -> Option<Identity> {
+    let config = Config::global();
+    let client = config.auth.as_ref().and_then(|auth| {
+        auth.clients
+            .iter()
+            .find(|c| cert.names().any(|n| n.eq_ignore_ascii_case(&c.subject)))
+    });
+    if let Some(c) = client {
+        return Some(Identity::new(
+            &c.name,
+            &c.domains,
+            c.redaction.as_ref(),
+            c.admin,
+        ));
+    }
+    if config
+        .auth
+        .as_ref()
+        .is_some_and(|a| !a.allow_unmapped_clients)
+    {
+        return None;
+    }
+    let name = cert.alt_names.first().or(cert.common_name.as_ref());
+    Some(Identity::new(
+        name.map_or("client certificate", String::as_str),
+        &[],
+        None,
+        false,
+    ))
+}
+
+/// Authenticates a request by its client certificate, or else by its bearer token
+pub async fn authenticate<B>(mut req: Request<B>, next: Next<B>) -> Response {
+    if let Some(Some(cert)) = req.extensions().get::<Option<ClientCert>>() {
+        let Some(identity) = client_identity(cert) else {
+            warn!(
+                "Rejected request to {} with an unknown client certificate {:?}",
+                req.uri().path(),
+                cert.names().collect::<Vec<_>>()
+            );
+            return unauthorized("unknown client certificate");
+        };
+        req.extensions_mut().insert(identity);
+        return next.run(req).await;
+    }
+    let config = Config::global();
+    let Some(auth) = &config.auth else {
+        req.extensions_mut().insert(Identity::anonymous());
+        return next.run(req).await;
+    };
+    let Some(token) = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+    else {
+        return unauthorized("missing bearer token");
+    };
+    let Some(config_token) = auth
+        .tokens
+        .iter()
+        .find(|t| constant_time_eq(t.token.as_bytes(), token.trim().as_bytes()))
+    else {
+        warn!(
+            "Rejected request to {} with an unknown token",
+            req.uri().path()
+        );
+        return unauthorized("invalid bearer token");
+    };
+    req.extensions_mut().insert(Identity::new(
+        &config_token.name,
+        &config_token.domains,
+        config_token.redaction.as_ref(),
+        config_token.admin,
+    ));
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn in_domains_matches_domains_and_subdomains() {
+        let domains = [String::from("example.com")];
+        assert!(in_domains("bob@example.com", &domains));
+        assert!(in_domains("Bob@EXAMPLE.com", &domains));
+        assert!(in_domains("bob@mail.example.com", &domains));
+        assert!(!in_domains("bob@evil-example.com", &domains));
+        assert!(!in_domains("bob@example.com.evil.org", &domains));
+        assert!(!in_domains("bob@com", &domains));
+        assert!(!in_domains("example.com", &domains));
+        assert!(!in_domains("bob@example.com", &[]));
+    }
+}
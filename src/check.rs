@@ -0,0 +1,237 @@
+use crate::config::Config;
+use crate::suppress;
+use crate::tls;
+use axum::http::HeaderValue;
+use log::{error, warn};
+use std::fmt;
+use std::fs::File;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Ok,
+    /// The service starts, but e.g. a file is skipped until it exists
+    Warning,
+    /// The service refuses to start
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Severity::Ok => "OK",
+            Severity::Warning => "WARN",
+            Severity::Error => "ERROR",
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Check {
+    pub severity: Severity,
+    /// What was checked, e.g. a file
+    pub subject: String,
+    pub message: String,
+}
+
+/// Results of checking a config against the system it runs on
+#[derive(Debug, Default)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    fn add(&mut self, severity: Severity, subject: impl fmt::Display, message: impl fmt::Display) {
+        self.checks.push(Check {
+            severity,
+            subject: subject.to_string(),
+            message: message.to_string(),
+        });
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.checks
+            .iter()
+            .filter(|c| c.severity == severity)
+            .count()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.count(Severity::Error) > 0
+    }
+
+    /// Logs the problems, for checks at startup
+    pub fn log(&self) {
+        for check in &self.checks {
+            match check.severity {
+                Severity::Ok => {}
+                Severity::Warning => warn!("Config: {}: {}", check.subject, check.message),
+                Severity::Error => error!("Config: {}: {}", check.subject, check.message),
+            }
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            writeln!(
+                f,
+                "{:<6}{}: {}",
+                check.severity, check.subject, check.message
+            )?;
+        }
+        write!(
+            f,
+            "{} ok, {} warnings, {} errors",
+            self.count(Severity::Ok),
+            self.count(Severity::Warning),
+            self.count(Severity::Error)
+        )
+    }
+}
+
+fn check_dir(report: &mut Report, name: &str, dir: &str) -> bool {
+    match std::fs::read_dir(dir) {
+        Ok(_) => {
+            report.add(Severity::Ok, name, format!("{dir} is readable"));
+            true
+        }
+        Err(why) => {
+            report.add(Severity::Error, name, format!("{dir} can't be read: {why}"));
+            false
+        }
+    }
+}
+
+/// Files that are missing are skipped when loading, or retried when tailing
+fn check_file(report: &mut Report, name: &str, file_path: &Path, when_missing: &str) {
+    match File::open(file_path) {
+        Ok(_) => report.add(
+            Severity::Ok,
+            name,
+            format!("{} is readable", file_path.display()),
+        ),
+        Err(why) => report.add(
+            Severity::Warning,
+            name,
+            format!(
+                "{} can't be read, {when_missing}: {why}",
+                file_path.display()
+            ),
+        ),
+    }
+}
+
+/// Checks that the listen address is valid, that sources are configured and readable,
+/// and that TLS, audit and suppression files can be used
+pub fn check(config: &Config) -> Report {
+    let mut report = Report::default();
+    let listen = format!("{}:{}", config.listen.ip, config.listen.port);
+    match listen.parse::<SocketAddr>() {
+        Ok(_) => report.add(Severity::Ok, "listen", &listen),
+        Err(why) => report.add(
+            Severity::Error,
+            "listen",
+            format!("{listen} is not a valid address: {why}"),
+        ),
+    }
+
+    let sources = [
+        ("log", &config.log.dir, &config.log.files, &config.log.tail),
+        (
+            "mail",
+            &config.mail.dir,
+            &config.mail.files,
+            &config.mail.tail,
+        ),
+    ];
+    if sources
+        .iter()
+        .all(|(_, _, files, tail)| files.is_empty() && tail.is_empty())
+    {
+        report.add(
+            Severity::Error,
+            "sources",
+            "no log or mail files are configured",
+        );
+    }
+    for (name, dir, files, tail) in sources {
+        if !check_dir(&mut report, &format!("{name}.dir"), dir) {
+            continue;
+        }
+        for file in files {
+            let file_path: PathBuf = [dir, file].iter().collect();
+            check_file(
+                &mut report,
+                &format!("{name}.files"),
+                &file_path,
+                "it will be skipped",
+            );
+        }
+        if !tail.is_empty() {
+            let file_path: PathBuf = [dir, tail].iter().collect();
+            check_file(
+                &mut report,
+                &format!("{name}.tail"),
+                &file_path,
+                "tailing is retried until it can",
+            );
+        }
+    }
+
+    if let Some(c) = &config.tls {
+        match tls::server_config(c) {
+            Ok(_) => report.add(
+                Severity::Ok,
+                "tls",
+                format!("{} and {} are valid", c.cert, c.key),
+            ),
+            Err(why) => report.add(Severity::Error, "tls", format!("{why:#}")),
+        }
+    }
+    for origin in config.cors_origins.iter().flatten() {
+        if let Err(why) = origin.parse::<HeaderValue>() {
+            report.add(
+                Severity::Error,
+                "cors_origins",
+                format!("{origin} is invalid: {why}"),
+            );
+        }
+    }
+    if let Some(audit) = &config.audit {
+        let dir = Path::new(&audit.file)
+            .parent()
+            .filter(|d| !d.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        match std::fs::metadata(dir) {
+            Ok(m) if m.is_dir() => report.add(Severity::Ok, "audit.file", &audit.file),
+            _ => report.add(
+                Severity::Error,
+                "audit.file",
+                format!(
+                    "directory {} of {} doesn't exist",
+                    dir.display(),
+                    audit.file
+                ),
+            ),
+        }
+    }
+    if config.limits.requests_per_minute == Some(0) {
+        report.add(
+            Severity::Error,
+            "limits.requests_per_minute",
+            "has to be at least 1, leave it out to not limit requests",
+        );
+    }
+    match suppress::read_rules(&config.suppression_file) {
+        Ok(rules) => report.add(
+            Severity::Ok,
+            "suppression_file",
+            format!("{} rules in {}", rules.len(), config.suppression_file),
+        ),
+        Err(why) => report.add(Severity::Error, "suppression_file", format!("{why:#}")),
+    }
+    report
+}
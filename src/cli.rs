@@ -0,0 +1,286 @@
+use crate::query::{MatchMode, SortOrder};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use serde_yaml::{Mapping, Value};
+use std::path::PathBuf;
+
+/// Indexes postfix mail logs and serves an API to search delivery attempts
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+    /// Serves the API if no command is given
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Validates the config and checks that its files and directories can be used, then prints a report
+    CheckConfig,
+    /// Loads the given files and prints the mails matching the filters, without the config or a server
+    Query(QueryArgs),
+    /// Queries a running server
+    Client(ClientArgs),
+}
+
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub enum OutputFormat {
+    /// One row per delivery attempt
+    #[default]
+    Table,
+    /// Results keyed by recipient, like find_mail
+    Json,
+}
+
+/// Filters of find_mail and find_sent_mail, named like their query parameters
+#[derive(Debug, clap::Args, Serialize)]
+pub struct FilterArgs {
+    /// Recipient address
+    #[arg(short, long)]
+    pub email_address_filter: Option<String>,
+    #[arg(short, long)]
+    pub subject_filter: Option<String>,
+    /// Sender address
+    #[arg(short, long)]
+    pub from_filter: Option<String>,
+    /// Words that all have to occur in the log line
+    #[arg(short, long)]
+    pub text_filter: Option<String>,
+    #[arg(long, default_value = "contains")]
+    pub email_address_match: MatchMode,
+    #[arg(long, default_value = "icontains")]
+    pub subject_match: MatchMode,
+    #[arg(long, default_value = "contains")]
+    pub from_match: MatchMode,
+    /// Earliest delivery attempt, e.g. 2024-01-31T00:00:00Z
+    #[arg(long)]
+    pub since: Option<DateTime<Utc>>,
+    /// Delivery attempts before this time
+    #[arg(long)]
+    pub until: Option<DateTime<Utc>>,
+    #[arg(long, default_value = "asc")]
+    pub order: SortOrder,
+}
+
+/// Filters of find_mail, applied to mails loaded from files
+#[derive(Debug, clap::Args)]
+pub struct QueryArgs {
+    /// Mail log files, plain or compressed with gzip or zstd
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+    /// Mailbox file to take subjects from, can be given multiple times
+    #[arg(long = "mail-file", value_name = "MAIL_FILE")]
+    pub subject_files: Vec<PathBuf>,
+    #[command(flatten)]
+    pub filters: FilterArgs,
+    /// Prints at most this many mails, all if not set
+    #[arg(short, long)]
+    pub limit: Option<usize>,
+    #[arg(short, long, value_enum, default_value_t)]
+    pub output: OutputFormat,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ClientOutput {
+    /// The delivery attempts of each address in order
+    #[default]
+    Terminal,
+    /// The response of the server, pages merged into one
+    Json,
+    /// One row per delivery attempt, with a header row
+    Csv,
+}
+
+/// Where a running server is reached and how to authenticate
+#[derive(Debug, clap::Args)]
+pub struct ClientArgs {
+    /// Base URL of the server
+    #[arg(long, env = "LMDB_URL", default_value = "http://localhost:8080")]
+    pub url: String,
+    /// API token, sent as bearer token
+    #[arg(long, env = "LMDB_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+    /// CA certificate to verify the server with, in addition to the system roots
+    #[arg(long, value_name = "PEM")]
+    pub ca_cert: Option<PathBuf>,
+    /// Client certificate, for servers that require one
+    #[arg(long, value_name = "PEM", requires = "key")]
+    pub cert: Option<PathBuf>,
+    /// Key of the client certificate
+    #[arg(long, value_name = "PEM", requires = "cert")]
+    pub key: Option<PathBuf>,
+    #[arg(short, long, value_enum, default_value_t)]
+    pub output: ClientOutput,
+    #[command(subcommand)]
+    pub command: ClientCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ClientCommand {
+    /// Finds mails by recipient, requires --email-address-filter
+    FindMail(ClientQueryArgs),
+    /// Finds everything a sender sent, requires --from-filter
+    FindSentMail(ClientQueryArgs),
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ClientQueryArgs {
+    #[command(flatten)]
+    pub filters: FilterArgs,
+    /// Mails per page
+    #[arg(short, long)]
+    pub limit: Option<usize>,
+    /// Continues after the last mail of a previous page
+    #[arg(long)]
+    pub cursor: Option<String>,
+    /// Fetches all pages instead of the first one
+    #[arg(long)]
+    pub all: bool,
+}
+
+/// Where the config is read from and values that override it, given before any command.
+/// Every override can also be set by its environment variable.
+#[derive(Debug, Clone, clap::Args)]
+pub struct ConfigArgs {
+    /// Config file, ./config.yaml if it exists. Without one, the config is built from overrides only.
+    #[arg(short, long, env = "LMDB_CONFIG")]
+    pub config: Option<PathBuf>,
+    #[arg(long, env = "LMDB_LISTEN_IP")]
+    listen_ip: Option<String>,
+    #[arg(long, env = "LMDB_LISTEN_PORT")]
+    listen_port: Option<String>,
+    #[arg(long, env = "LMDB_LOG_DIR")]
+    log_dir: Option<String>,
+    /// Log files to load, comma separated
+    #[arg(long, env = "LMDB_LOG_FILES", value_delimiter = ',')]
+    log_files: Option<Vec<String>>,
+    #[arg(long, env = "LMDB_LOG_TAIL")]
+    log_tail: Option<String>,
+    #[arg(long, env = "LMDB_MAIL_DIR")]
+    mail_dir: Option<String>,
+    /// Mail files to load, comma separated
+    #[arg(long, env = "LMDB_MAIL_FILES", value_delimiter = ',')]
+    mail_files: Option<Vec<String>>,
+    #[arg(long, env = "LMDB_MAIL_TAIL")]
+    mail_tail: Option<String>,
+    /// Seconds
+    #[arg(long, env = "LMDB_MAIL_PARSING_DELAY")]
+    mail_parsing_delay: Option<u64>,
+    #[arg(long, env = "LMDB_TLS_CERT")]
+    tls_cert: Option<String>,
+    #[arg(long, env = "LMDB_TLS_KEY")]
+    tls_key: Option<String>,
+    #[arg(long, env = "LMDB_TLS_CLIENT_CA")]
+    tls_client_ca: Option<String>,
+    #[arg(long, env = "LMDB_AUDIT_FILE")]
+    audit_file: Option<String>,
+    #[arg(long, env = "LMDB_SUPPRESSION_FILE")]
+    suppression_file: Option<String>,
+    /// Overrides any config value by its dotted path, e.g. limits.max_matched=5000. Values are parsed as YAML.
+    #[arg(long = "set", value_name = "PATH=VALUE")]
+    set: Vec<String>,
+}
+
+impl ConfigArgs {
+    /// Overrides by dotted path, the generic ones last so they win
+    fn overrides(&self) -> Result<Vec<(String, Value)>> {
+        let string = |s: &Option<String>| s.clone().map(Value::String);
+        let list = |l: &Option<Vec<String>>| {
+            l.clone()
+                .map(|l| Value::Sequence(l.into_iter().map(Value::String).collect()))
+        };
+        let overrides = [
+            ("listen.ip", string(&self.listen_ip)),
+            ("listen.port", string(&self.listen_port)),
+            ("log.dir", string(&self.log_dir)),
+            ("log.files", list(&self.log_files)),
+            ("log.tail", string(&self.log_tail)),
+            ("mail.dir", string(&self.mail_dir)),
+            ("mail.files", list(&self.mail_files)),
+            ("mail.tail", string(&self.mail_tail)),
+            (
+                "mail_parsing_delay",
+                self.mail_parsing_delay.map(Value::from),
+            ),
+            ("tls.cert", string(&self.tls_cert)),
+            ("tls.key", string(&self.tls_key)),
+            ("tls.client_ca", string(&self.tls_client_ca)),
+            ("audit.file", string(&self.audit_file)),
+            ("suppression_file", string(&self.suppression_file)),
+        ];
+        let mut result: Vec<(String, Value)> = overrides
+            .into_iter()
+            .filter_map(|(path, value)| value.map(|v| (path.to_string(), v)))
+            .collect();
+        for set in &self.set {
+            let Some((path, value)) = set.split_once('=') else {
+                bail!("--set {set} is not of the form PATH=VALUE");
+            };
+            let value = serde_yaml::from_str(value).with_context(|| format!("in --set {set}"))?;
+            result.push((path.to_string(), value));
+        }
+        Ok(result)
+    }
+
+    /// The given config file, or ./config.yaml if it exists
+    pub fn file_path(&self) -> Option<PathBuf> {
+        let default_path = PathBuf::from("./config.yaml");
+        match &self.config {
+            Some(path) => Some(path.clone()),
+            None => default_path.is_file().then_some(default_path),
+        }
+    }
+
+    /// Where the config comes from, for errors
+    pub fn source(&self) -> String {
+        match self.file_path() {
+            Some(path) => path.display().to_string(),
+            None => String::from("command line and environment"),
+        }
+    }
+
+    /// Reads the config file as YAML and applies the overrides.
+    /// The file is returned as is without overrides, so errors point at its lines.
+    pub fn yaml(&self) -> Result<String> {
+        let content = match self.file_path() {
+            Some(path) => std::fs::read_to_string(&path)
+                .with_context(|| format!("while reading config {}", path.display()))?,
+            None => String::new(),
+        };
+        let overrides = self.overrides()?;
+        if overrides.is_empty() {
+            return Ok(content);
+        }
+        let mut yaml = match serde_yaml::from_str(&content)? {
+            Value::Null => Value::Mapping(Mapping::new()),
+            yaml => yaml,
+        };
+        for (path, value) in overrides {
+            set_path(&mut yaml, &path, value)
+                .with_context(|| format!("while overriding {path}"))?;
+        }
+        Ok(serde_yaml::to_string(&yaml)?)
+    }
+}
+
+/// Sets the value at a dotted path, creating the mappings along the way
+fn set_path(yaml: &mut Value, path: &str, value: Value) -> Result<()> {
+    let mut current = yaml;
+    for key in path.split('.') {
+        if current.is_null() {
+            *current = Value::Mapping(Mapping::new());
+        }
+        let Value::Mapping(mapping) = current else {
+            bail!("{key} is not within a mapping");
+        };
+        current = mapping
+            .entry(Value::String(key.to_string()))
+            .or_insert(Value::Null);
+    }
+    *current = value;
+    Ok(())
+}
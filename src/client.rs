@@ -0,0 +1,195 @@
+use crate::cli::{ClientArgs, ClientCommand, ClientOutput, ClientQueryArgs};
+use crate::export::NEXT_CURSOR;
+use crate::mail::Mail;
+use anyhow::{bail, Context, Result};
+use reqwest::{Certificate, Client, Identity, RequestBuilder, Response};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+/// Response of find_mail and find_sent_mail
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct FindMailResponse {
+    #[serde(default)]
+    results: BTreeMap<String, Vec<Mail>>,
+    #[serde(default)]
+    total: usize,
+    #[serde(default)]
+    total_addresses: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    warming_up: bool,
+}
+
+/// Pagination and format of a query, next to its filters
+#[derive(Debug, Serialize)]
+struct Page<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cursor: Option<&'a str>,
+    format: &'static str,
+}
+
+fn client(args: &ClientArgs) -> Result<Client> {
+    let mut builder = Client::builder();
+    if let Some(ca_cert) = &args.ca_cert {
+        let pem = std::fs::read(ca_cert)
+            .with_context(|| format!("while reading {}", ca_cert.display()))?;
+        builder = builder.add_root_certificate(Certificate::from_pem(&pem)?);
+    }
+    if let (Some(cert), Some(key)) = (&args.cert, &args.key) {
+        let mut pem =
+            std::fs::read(cert).with_context(|| format!("while reading {}", cert.display()))?;
+        pem.extend(std::fs::read(key).with_context(|| format!("while reading {}", key.display()))?);
+        builder = builder.identity(Identity::from_pem(&pem)?);
+    }
+    Ok(builder.build()?)
+}
+
+/// Sends the request and fails with the error the server gave, if any
+async fn send(request: RequestBuilder) -> Result<Response> {
+    let response = request.send().await?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    let error = serde_json::from_str::<serde_json::Value>(&body)
+        .ok()
+        .and_then(|v| v.pointer("/error/message")?.as_str().map(String::from))
+        .unwrap_or(body);
+    bail!("{status}: {error}")
+}
+
+/// Prints the delivery attempts of each address in the order they were returned
+fn print_timelines(response: &FindMailResponse, out: &mut impl Write) -> io::Result<()> {
+    for (address, mails) in &response.results {
+        let plural = if mails.len() == 1 { "" } else { "s" };
+        writeln!(out, "{address} ({} attempt{plural})", mails.len())?;
+        for mail in mails {
+            let timestamp = mail
+                .timestamp
+                .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_else(|| String::from("-"));
+            write!(
+                out,
+                "  {timestamp:<19}  {:<8}  {:<5}  {}",
+                mail.status.as_deref().unwrap_or("-"),
+                mail.dsn.as_deref().unwrap_or("-"),
+                mail.id,
+            )?;
+            match mail.from.as_deref() {
+                Some("") => write!(out, "  from <>")?,
+                Some(from) => write!(out, "  from {from}")?,
+                None => {}
+            }
+            // Results of find_sent_mail are keyed by sender
+            if mail.to != *address {
+                write!(out, "  to {}", mail.to)?;
+            }
+            if let Some(subject) = &mail.subject {
+                write!(out, "  \"{subject}\"")?;
+            }
+            writeln!(out)?;
+        }
+        writeln!(out)?;
+    }
+    writeln!(
+        out,
+        "{} mails, {} addresses",
+        response.total, response.total_addresses
+    )?;
+    if let Some(cursor) = &response.next_cursor {
+        writeln!(
+            out,
+            "More results, continue with --cursor {cursor} or fetch all with --all"
+        )?;
+    }
+    if response.warming_up {
+        writeln!(
+            out,
+            "The server is still loading files, results may be incomplete"
+        )?;
+    }
+    Ok(())
+}
+
+/// Queries find_mail or find_sent_mail, following the cursors if all pages are wanted
+async fn find(
+    client: &Client,
+    args: &ClientArgs,
+    endpoint: &str,
+    query: &ClientQueryArgs,
+) -> Result<()> {
+    let url = format!("{}/{endpoint}", args.url.trim_end_matches('/'));
+    let format = match args.output {
+        ClientOutput::Csv => "csv",
+        _ => "json",
+    };
+    let mut out = io::stdout().lock();
+    let mut merged = FindMailResponse::default();
+    let mut cursor = query.cursor.clone();
+    let mut first = true;
+    loop {
+        let mut request = client.get(&url).query(&query.filters).query(&Page {
+            limit: query.limit,
+            cursor: cursor.as_deref(),
+            format,
+        });
+        if let Some(token) = &args.token {
+            request = request.bearer_auth(token);
+        }
+        let response = send(request).await?;
+        let next_cursor = if args.output == ClientOutput::Csv {
+            let next_cursor = response
+                .headers()
+                .get(&NEXT_CURSOR)
+                .and_then(|c| c.to_str().ok())
+                .map(String::from);
+            let body = response.text().await?;
+            // Pages after the first repeat the header row
+            let rows = if first {
+                &body
+            } else {
+                body.split_once('\n').map_or("", |(_, rows)| rows)
+            };
+            out.write_all(rows.as_bytes())?;
+            next_cursor
+        } else {
+            let page: FindMailResponse = response.json().await?;
+            for (address, mails) in page.results {
+                merged.results.entry(address).or_default().extend(mails);
+            }
+            merged.total = page.total;
+            merged.total_addresses = page.total_addresses;
+            merged.warming_up |= page.warming_up;
+            merged.next_cursor = page.next_cursor.clone();
+            page.next_cursor
+        };
+        first = false;
+        match next_cursor {
+            Some(next) if query.all => cursor = Some(next),
+            _ => break,
+        }
+    }
+    match args.output {
+        ClientOutput::Terminal => print_timelines(&merged, &mut out)?,
+        ClientOutput::Json => {
+            serde_json::to_writer_pretty(&mut out, &merged)?;
+            writeln!(out)?;
+        }
+        ClientOutput::Csv => {}
+    }
+    Ok(())
+}
+
+/// Runs a command of the client against a running server
+pub async fn run(args: &ClientArgs) -> Result<()> {
+    let client = client(args)?;
+    match &args.command {
+        ClientCommand::FindMail(query) => find(&client, args, "find_mail", query).await,
+        ClientCommand::FindSentMail(query) => find(&client, args, "find_sent_mail", query).await,
+    }
+}
@@ -1,38 +1,174 @@
+use crate::cli::ConfigArgs;
+use crate::redact::{AddressRedaction, RedactionProfile, Redactor};
 use crate::CONFIG;
-use anyhow::Context;
+use anyhow::{bail, Context};
 use serde::Deserialize;
-use std::fs::File;
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::sync::Arc;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct ConfigLogs {
     pub dir: String,
     pub files: Vec<String>,
     pub tail: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct ConfigMails {
     pub dir: String,
     pub files: Vec<String>,
     pub tail: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct ConfigListen {
     pub ip: String,
     pub port: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct ConfigTls {
     pub cert: String,
     pub key: String,
+    /// CA bundle to verify client certificates with, clients don't need a certificate if not set
+    pub client_ca: Option<String>,
+    /// Also accept clients without a certificate, they have to authenticate with a token instead
+    #[serde(default)]
+    pub optional_client_cert: bool,
+}
+
+/// API tokens and client certificates, the API is open to anyone if no auth is configured
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigAuth {
+    #[serde(default)]
+    pub tokens: Vec<ConfigToken>,
+    /// Identities of client certificates. Certificates that match none of them are rejected
+    #[serde(default)]
+    pub clients: Vec<ConfigClient>,
+    /// Gives certificates that match no client full access instead of rejecting them
+    #[serde(default)]
+    pub allow_unmapped_clients: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigToken {
+    /// Identifies the token holder in logs
+    pub name: String,
+    pub token: String,
+    /// Recipient or sender domains (including subdomains) this token may see, all if empty
+    #[serde(default)]
+    pub domains: Vec<String>,
+    /// Name of the redaction profile applied to mails returned to this token
+    pub redaction: Option<String>,
+    /// May erase addresses and import mail
+    #[serde(default)]
+    pub admin: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigClient {
+    /// Identifies the certificate holder in logs
+    pub name: String,
+    /// Common name or subject alternative name of the client certificate
+    pub subject: String,
+    /// Recipient or sender domains (including subdomains) this client may see, all if empty
+    #[serde(default)]
+    pub domains: Vec<String>,
+    /// Name of the redaction profile applied to mails returned to this client
+    pub redaction: Option<String>,
+    /// May erase addresses and import mail
+    #[serde(default)]
+    pub admin: bool,
+}
+
+/// Named redaction profiles that tokens and clients can be assigned
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigRedaction {
+    /// Secret for hashing addresses, required by profiles that hash
+    pub key: Option<String>,
+    pub profiles: BTreeMap<String, RedactionProfile>,
+}
+
+/// JSON lines log of every query, with its own size based rotation
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigAudit {
+    pub file: String,
+    /// Rotate the file when it would grow beyond this size
+    #[serde(default = "default_audit_max_size_mb")]
+    pub max_size_mb: u64,
+    /// Rotated files to keep
+    #[serde(default = "default_audit_keep")]
+    pub keep: usize,
+}
+
+fn default_audit_max_size_mb() -> u64 {
+    100
+}
+
+fn default_audit_keep() -> usize {
+    10
+}
+
+/// Rate limits per identity, or per client IP for anonymous requests, and bounds on the cost of a query
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigLimits {
+    /// Requests to query endpoints per minute, unlimited if not set
+    pub requests_per_minute: Option<u32>,
+    /// Requests that can be made at once before the rate applies, defaults to requests_per_minute
+    pub burst: Option<u32>,
+    /// Minimum length of contains and regex filters
+    pub min_filter_length: usize,
+    /// Mails a search may match before it is rejected as too broad, stats only count them.
+    /// Formerly max_scanned, which is still accepted.
+    #[serde(alias = "max_scanned")]
+    pub max_matched: usize,
+    /// Seconds a query may hold the DB before it is rejected as too broad
+    pub query_timeout: u64,
+    /// Largest file that can be uploaded to be imported, as stored, i.e. compressed if it is
+    pub max_upload_mb: u64,
+}
+
+impl Default for ConfigLimits {
+    fn default() -> Self {
+        ConfigLimits {
+            requests_per_minute: None,
+            burst: None,
+            min_filter_length: 0,
+            max_matched: 1_000_000,
+            query_timeout: 10,
+            max_upload_mb: 1024,
+        }
+    }
+}
+
+fn default_suppression_file() -> String {
+    String::from("./suppressions.txt")
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub tls: Option<ConfigTls>,
+    pub auth: Option<ConfigAuth>,
+    pub audit: Option<ConfigAudit>,
+    pub redaction: Option<ConfigRedaction>,
+    #[serde(default)]
+    pub limits: ConfigLimits,
+    /// Erased addresses and domains, one per line, that are never ingested again
+    #[serde(default = "default_suppression_file")]
+    pub suppression_file: String,
+    /// Origins allowed to query the API from a browser, any if not set
+    pub cors_origins: Option<Vec<String>>,
     pub log: ConfigLogs,
     pub mail: ConfigMails,
     pub listen: ConfigListen,
@@ -40,15 +176,68 @@ pub struct Config {
 }
 
 impl Config {
-    pub fn global() -> &'static Config {
-        CONFIG.get().expect("Config is not initialized")
+    /// The current config. Hold on to it for a consistent view while it may be reloaded.
+    pub fn global() -> Arc<Config> {
+        CONFIG
+            .get()
+            .expect("Config is not initialized")
+            .read()
+            .clone()
+    }
+
+    /// Replaces the current config and returns the previous one
+    pub fn replace(config: Config) -> Arc<Config> {
+        let lock = CONFIG.get().expect("Config is not initialized");
+        std::mem::replace(&mut *lock.write(), Arc::new(config))
+    }
+
+    pub fn redactor(&self, profile: &str) -> Option<Redactor> {
+        let redaction = self.redaction.as_ref()?;
+        let profile = redaction.profiles.get(profile)?;
+        Some(Redactor::new(
+            profile.clone(),
+            redaction.key.as_deref().unwrap_or_default(),
+        ))
+    }
+
+    /// Checks references between sections, so a typo can't silently disable redaction
+    fn validate(&self) -> anyhow::Result<()> {
+        let redaction = self.redaction.as_ref();
+        if let Some(r) = redaction {
+            let hashes = r
+                .profiles
+                .values()
+                .any(|p| p.addresses == AddressRedaction::Hash);
+            if hashes && r.key.as_deref().unwrap_or_default().is_empty() {
+                bail!("redaction.key is required by profiles that hash addresses");
+            }
+        }
+        let Some(auth) = &self.auth else {
+            return Ok(());
+        };
+        let assigned = auth
+            .tokens
+            .iter()
+            .map(|t| (&t.name, &t.redaction))
+            .chain(auth.clients.iter().map(|c| (&c.name, &c.redaction)));
+        for (name, profile) in assigned {
+            let Some(profile) = profile else {
+                continue;
+            };
+            if !redaction.is_some_and(|r| r.profiles.contains_key(profile)) {
+                bail!("{name} uses the unknown redaction profile {profile}");
+            }
+        }
+        Ok(())
     }
 }
 
-pub fn read_config() -> anyhow::Result<Config> {
-    let file_path = PathBuf::from("./config.yaml");
-    let f = File::open(file_path).with_context(|| "while reading config")?;
-    let config: Config =
-        serde_yaml::from_reader(f).with_context(|| "while reading config & deserializing")?;
+/// Reads the config file and applies the overrides from the command line and environment
+pub fn read_config(args: &ConfigArgs) -> anyhow::Result<Config> {
+    // Deserialized from text, so plain scalars like `port: 8080` can still be read as strings
+    let yaml = args.yaml()?;
+    let config: Config = serde_yaml::from_str(&yaml)
+        .with_context(|| format!("invalid config in {}", args.source()))?;
+    config.validate()?;
     Ok(config)
 }
@@ -1,71 +1,726 @@
-use crate::mail::{Mail, MAIL_DB};
-use axum::extract::Query;
-use axum::http::StatusCode;
-use axum::response::IntoResponse;
+use crate::audit::AuditResults;
+use crate::auth::Identity;
+use crate::config::Config;
+use crate::error::{ApiError, ApiQuery, ErrorCode};
+use crate::export::{export, Format, Rows};
+use crate::health::HEALTH;
+use crate::import::ImportReport;
+use crate::import::{Import, ImportKind, UploadError};
+use crate::index::tokenize;
+use crate::mail::{Mail, MAIL_DB, MAIL_EVENTS};
+use crate::metrics;
+use crate::query::{
+    paginate, Budget, Cursor, MailFilter, MatchMode, Matcher, SortOrder, DEFAULT_LIMIT, MAX_LIMIT,
+};
+use crate::stats::{aggregate, StatsGroup, StatsGroupBy, Window};
+use crate::suppress::{self, Rule};
+use anyhow::{bail, Context};
+use axum::extract::rejection::PathRejection;
+use axum::extract::{BodyStream, Extension, Path};
+use axum::http::{StatusCode, Uri};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
 use axum::Json;
-use log::info;
-use rustc_hash::FxHashMap;
+use chrono::{DateTime, Duration, Utc};
+use log::{error, info};
+use rustc_hash::FxHashSet;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::time::Duration as StdDuration;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Debug, Deserialize)]
+/// Compiles an optional filter of a query, naming the parameter in the error.
+/// Contains and regex filters that are too short to narrow down a query are rejected.
+fn compile(
+    filter: &Option<String>,
+    mode: MatchMode,
+    name: &str,
+) -> Result<Option<Matcher>, ApiError> {
+    let min_length = Config::global().limits.min_filter_length;
+    let compiled = || -> anyhow::Result<Option<Matcher>> {
+        if let Some(f) = filter {
+            let broad = matches!(
+                mode,
+                MatchMode::Contains | MatchMode::IContains | MatchMode::Regex
+            );
+            if broad && f.chars().count() < min_length {
+                bail!("{name} has to be at least {min_length} characters long");
+            }
+        }
+        filter
+            .as_deref()
+            .map(|f| Matcher::new(mode, f))
+            .transpose()
+            .with_context(|| name.to_string())
+    };
+    compiled().map_err(|why| ApiError::invalid_parameter(name, why))
+}
+
+/// Compiles an address filter. Identities that see redacted addresses may only match whole
+/// addresses or domains, partial matches could recover an address one character at a time.
+fn compile_address(
+    filter: &Option<String>,
+    mode: MatchMode,
+    name: &str,
+    identity: &Identity,
+) -> Result<Option<Matcher>, ApiError> {
+    let redacted = identity
+        .redactor
+        .as_ref()
+        .is_some_and(|r| r.redacts_addresses());
+    let whole = matches!(
+        mode,
+        MatchMode::Exact | MatchMode::IExact | MatchMode::Domain
+    );
+    if redacted && filter.is_some() && !whole {
+        let parameter = name.replace("_filter", "_match");
+        return Err(ApiError::invalid_parameter(
+            &parameter,
+            format!(
+                "{parameter} has to be exact, iexact or domain, because addresses are redacted"
+            ),
+        ));
+    }
+    compile(filter, mode, name)
+}
+
+/// Budget of a query as configured in the limits
+pub(crate) fn budget() -> Budget {
+    let limits = &Config::global().limits;
+    Budget::new(
+        limits.max_matched,
+        StdDuration::from_secs(limits.query_timeout),
+    )
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct FindMailQuery {
-    email_address_filter: String,
+    /// Recipient address, required by find_mail unless a non-empty subject, sender or text filter is given.
+    /// Empty matches every recipient
+    email_address_filter: Option<String>,
     subject_filter: Option<String>,
+    /// Envelope sender, required by find_sent_mail
+    from_filter: Option<String>,
+    /// Words that all have to occur in the log line, case-insensitively
+    text_filter: Option<String>,
+    /// Defaults to contains
+    email_address_match: Option<MatchMode>,
+    /// Defaults to icontains
+    subject_match: Option<MatchMode>,
+    /// Defaults to contains
+    from_match: Option<MatchMode>,
+    /// Earliest delivery attempt
+    since: Option<DateTime<Utc>>,
+    /// Delivery attempts before this time
+    until: Option<DateTime<Utc>>,
+    /// Page size, defaults to 1000 and is at most 10000. Exports return all results if not given
+    limit: Option<usize>,
+    /// The next_cursor of the previous page
+    cursor: Option<String>,
+    #[serde(default)]
+    order: SortOrder,
+    #[serde(default)]
+    format: Format,
 }
 
-#[derive(Serialize)]
+impl FindMailQuery {
+    /// Whether a non-empty filter other than the recipient narrows down the query
+    fn has_other_filter(&self) -> bool {
+        [&self.subject_filter, &self.from_filter, &self.text_filter]
+            .into_iter()
+            .any(|f| f.as_deref().is_some_and(|f| !f.is_empty()))
+    }
+
+    /// Compiles the filters of this query, restricted to what the identity may see
+    fn filter(&self, identity: &Identity) -> Result<MailFilter, ApiError> {
+        let subject_mode = self.subject_match.unwrap_or(MatchMode::IContains);
+        if subject_mode == MatchMode::Domain {
+            return Err(ApiError::invalid_parameter(
+                "subject_match",
+                "subject_match does not support domain",
+            ));
+        }
+        let redactor = identity.redactor.as_ref();
+        if self.subject_filter.is_some() && redactor.is_some_and(|r| r.drops_subjects()) {
+            return Err(ApiError::invalid_parameter(
+                "subject_filter",
+                "subject_filter is not available, because subjects are redacted",
+            ));
+        }
+        let text: Vec<String> = self
+            .text_filter
+            .as_deref()
+            .map(|t| tokenize(t).collect())
+            .unwrap_or_default();
+        // Lines hide the addresses in them, so they can't be probed for either
+        if text.iter().any(|t| t.contains('@'))
+            && redactor.is_some_and(|r| r.redacts_any_addresses())
+        {
+            return Err(ApiError::invalid_parameter(
+                "text_filter",
+                "text_filter can't contain addresses, because addresses are redacted",
+            ));
+        }
+        Ok(MailFilter {
+            address: compile_address(
+                &self.email_address_filter,
+                self.email_address_match.unwrap_or(MatchMode::Contains),
+                "email_address_filter",
+                identity,
+            )?,
+            subject: compile(&self.subject_filter, subject_mode, "subject_filter")?,
+            from: compile_address(
+                &self.from_filter,
+                self.from_match.unwrap_or(MatchMode::Contains),
+                "from_filter",
+                identity,
+            )?,
+            text,
+            domains: identity.domains.clone(),
+            since: self.since,
+            until: self.until,
+        })
+    }
+}
+
+#[derive(Serialize, ToSchema)]
 pub struct FindMailResponse {
+    /// Delivery attempts of the page, keyed by recipient or by sender. Empty if nothing matches
+    results: BTreeMap<String, Vec<Mail>>,
+    /// Amount of mails matching the query, across all pages
+    total: usize,
+    /// Amount of email addresses the results are grouped by, across all pages
+    total_addresses: usize,
     #[serde(skip_serializing_if = "Option::is_none")]
-    results: Option<FxHashMap<String, Vec<Mail>>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    error: Option<String>,
+    next_cursor: Option<String>,
+    /// Set while the configured files are still being loaded, so results may be incomplete
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    warming_up: bool,
+}
+
+/// What the results of a query are keyed by
+#[derive(Debug, Clone, Copy)]
+enum GroupBy {
+    Recipient,
+    Sender,
+}
+
+impl GroupBy {
+    fn key<'a>(&self, mail: &'a Mail) -> &'a str {
+        match self {
+            GroupBy::Recipient => &mail.to,
+            GroupBy::Sender => match mail.from.as_deref() {
+                Some("") | None => "<>",
+                Some(from) => from,
+            },
+        }
+    }
 }
 
-pub async fn find_mail(query: Query<FindMailQuery>) -> impl IntoResponse {
+/// Find mails by recipient, optionally filtered by subject and sender, or by subject, sender or text alone
+#[utoipa::path(
+    get,
+    path = "/find_mail",
+    params(FindMailQuery),
+    responses(
+        (status = 200, description = "Delivery attempts keyed by recipient, or rows with format ndjson or csv", body = FindMailResponse),
+        (status = 400, description = "A parameter is missing or invalid", body = ErrorResponse),
+        (status = 422, description = "The query is too broad", body = ErrorResponse),
+    )
+)]
+pub async fn find_mail(
+    Extension(identity): Extension<Identity>,
+    query: ApiQuery<FindMailQuery>,
+) -> Result<Response, ApiError> {
+    if query.email_address_filter.is_none() && !query.has_other_filter() {
+        return Err(ApiError::missing_parameter("email_address_filter"));
+    }
+    search(&query, &identity, GroupBy::Recipient)
+}
+
+/// Find everything a sender sent, optionally filtered by recipient and subject
+#[utoipa::path(
+    get,
+    path = "/find_sent_mail",
+    params(FindMailQuery),
+    responses(
+        (status = 200, description = "Delivery attempts keyed by sender, or rows with format ndjson or csv", body = FindMailResponse),
+        (status = 400, description = "A parameter is missing or invalid", body = ErrorResponse),
+        (status = 422, description = "The query is too broad", body = ErrorResponse),
+    )
+)]
+pub async fn find_sent_mail(
+    Extension(identity): Extension<Identity>,
+    query: ApiQuery<FindMailQuery>,
+) -> Result<Response, ApiError> {
+    if query.from_filter.is_none() {
+        return Err(ApiError::missing_parameter("from_filter"));
+    }
+    search(&query, &identity, GroupBy::Sender)
+}
+
+/// Stream new deliveries, status updates and subject or sender updates that match the filters
+/// as Server-Sent Events. Pagination parameters are ignored.
+#[utoipa::path(
+    get,
+    path = "/stream_mail",
+    params(FindMailQuery),
+    responses(
+        (status = 200, description = "Events new, status_update and updated with a mail, and lagged with the amount of missed events", body = Mail, content_type = "text/event-stream"),
+        (status = 400, description = "A parameter is invalid", body = ErrorResponse),
+    )
+)]
+pub async fn stream_mail(
+    Extension(identity): Extension<Identity>,
+    query: ApiQuery<FindMailQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, serde_json::Error>>>, ApiError> {
+    let filter = query.filter(&identity)?;
+    info!(
+        "{} is streaming mail for {} with filter {} from {}",
+        identity.name,
+        query.email_address_filter.as_deref().unwrap_or_default(),
+        query.subject_filter.as_deref().unwrap_or_default(),
+        query.from_filter.as_deref().unwrap_or_default(),
+    );
+    let events = BroadcastStream::new(MAIL_EVENTS.subscribe()).filter_map(move |event| {
+        match event {
+            Ok(event) if filter.matches(&event.mail) => Some(
+                Event::default()
+                    .event(event.kind.as_str())
+                    .json_data(identity.redact(event.mail)),
+            ),
+            Ok(_) => None,
+            // The client is too slow to keep up, let it know how many events it missed
+            Err(BroadcastStreamRecvError::Lagged(missed)) => Some(Ok(Event::default()
+                .event("lagged")
+                .data(missed.to_string()))),
+        }
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+fn search(
+    query: &FindMailQuery,
+    identity: &Identity,
+    group_by: GroupBy,
+) -> Result<Response, ApiError> {
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(str::parse::<Cursor>)
+        .transpose()
+        .map_err(|why| ApiError::invalid_parameter("cursor", why))?;
+    let filter = query.filter(identity)?;
     let mdb = MAIL_DB.lock();
+    let email_address_filter = query.email_address_filter.clone().unwrap_or_default();
     let subject_filter = query.subject_filter.clone().unwrap_or_default();
+    let from_filter = query.from_filter.clone().unwrap_or_default();
+    let text_filter = query.text_filter.clone().unwrap_or_default();
     info!(
-        "Searching mail for {} with filter {}",
-        query.email_address_filter, subject_filter
+        "{} is searching mail for {} with filter {} from {} containing {}",
+        identity.name, email_address_filter, subject_filter, from_filter, text_filter
     );
-    let mail_db_results: FxHashMap<String, Vec<Mail>> = mdb
+    let matches = filter.find(&mdb, &budget())?;
+    if query.format != Format::Json {
+        // Exports aren't capped by MAX_LIMIT, their rows are read page by page while streaming
+        let limit = query.limit.unwrap_or(usize::MAX).max(1);
+        let total = matches.len();
+        let next_cursor = if limit < total {
+            paginate(matches, query.order, cursor.as_ref(), limit).next_cursor
+        } else {
+            None
+        };
+        drop(mdb);
+        let rows = Rows::new(
+            filter,
+            identity.clone(),
+            query.order,
+            cursor,
+            limit,
+            query.format,
+        );
+        return Ok((
+            Extension(AuditResults(total)),
+            export(rows, total, next_cursor),
+        )
+            .into_response());
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let total_addresses = matches
         .iter()
-        .filter(|(k, _)| k.contains(&query.email_address_filter))
-        .map(|(k, v)| (k.clone(), v.clone()))
-        .map(|(k, mut v)| {
-            if query.subject_filter.is_some() {
-                v.retain(|mail| match &mail.subject {
-                    Some(v) => v.to_lowercase().contains(&subject_filter.to_lowercase()),
-                    None => false,
-                });
-            }
-            (k, v)
-        })
-        .map(|(k, mut v)| {
-            v.sort_by(|a, b| a.line.cmp(&b.line));
-            (k, v)
-        })
-        .filter(|(_, v)| !v.is_empty())
+        .map(|mail| group_by.key(mail))
+        .collect::<FxHashSet<_>>()
+        .len();
+    let page = paginate(matches, query.order, cursor.as_ref(), limit);
+
+    let mails: Vec<Mail> = page
+        .mails
+        .into_iter()
+        .map(|mail| identity.redact(mail.clone()))
         .collect();
+    let mut mail_db_results: BTreeMap<String, Vec<Mail>> = BTreeMap::new();
+    for mail in mails {
+        mail_db_results
+            .entry(group_by.key(&mail).to_string())
+            .or_default()
+            .push(mail);
+    }
+    Ok((
+        StatusCode::OK,
+        Extension(AuditResults(page.total)),
+        Json(FindMailResponse {
+            results: mail_db_results,
+            total: page.total,
+            total_addresses,
+            next_cursor: page.next_cursor.map(|c| c.to_string()),
+            warming_up: HEALTH.is_warming_up(),
+        }),
+    )
+        .into_response())
+}
 
-    if mail_db_results.is_empty() {
-        (
-            StatusCode::NOT_FOUND,
-            Json(FindMailResponse {
-                results: None,
-                error: Some(format!(
-                    "No mails found for query '{}' with subject filter '{}'",
-                    &query.email_address_filter, subject_filter
-                )),
-            }),
-        )
-    } else {
-        (
-            StatusCode::OK,
-            Json(FindMailResponse {
-                results: Some(mail_db_results),
-                error: None,
-            }),
-        )
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StatsQuery {
+    group_by: StatsGroupBy,
+    /// Start of the window, defaults to 24 hours before `until`
+    since: Option<DateTime<Utc>>,
+    /// End of the window, defaults to now
+    until: Option<DateTime<Utc>>,
+    /// Length of a bucket in seconds, defaults to an hour and is at most the window
+    bucket: Option<i64>,
+    email_address_filter: Option<String>,
+    email_address_match: Option<MatchMode>,
+    from_filter: Option<String>,
+    from_match: Option<MatchMode>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct StatsResponse {
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+    /// Length of a bucket in seconds
+    bucket: i64,
+    /// Amount of messages per recipient within the window, across all groups
+    total: usize,
+    groups: BTreeMap<String, StatsGroup>,
+    /// Set while the configured files are still being loaded, so counts may be incomplete
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    warming_up: bool,
+}
+
+/// Count messages per recipient and group over a time window, in time buckets
+#[utoipa::path(
+    get,
+    path = "/stats",
+    params(StatsQuery),
+    responses(
+        (status = 200, description = "A series of counts per group", body = StatsResponse),
+        (status = 400, description = "A parameter is missing or invalid", body = ErrorResponse),
+        (status = 422, description = "The query is too broad", body = ErrorResponse),
+    )
+)]
+pub async fn stats(
+    Extension(identity): Extension<Identity>,
+    query: ApiQuery<StatsQuery>,
+) -> Result<Response, ApiError> {
+    let until = query.until.unwrap_or_else(Utc::now);
+    let since = match query.since {
+        Some(since) => since,
+        None => until
+            .checked_sub_signed(Duration::hours(24))
+            .ok_or_else(|| ApiError::invalid_parameter("until", "until is out of range"))?,
+    };
+    let window = Window::new(since, until, query.bucket.unwrap_or(3600))
+        .map_err(|why| ApiError::invalid_parameter(why.parameter(), why))?;
+    let filter = MailFilter {
+        address: compile_address(
+            &query.email_address_filter,
+            query.email_address_match.unwrap_or(MatchMode::Contains),
+            "email_address_filter",
+            &identity,
+        )?,
+        from: compile_address(
+            &query.from_filter,
+            query.from_match.unwrap_or(MatchMode::Contains),
+            "from_filter",
+            &identity,
+        )?,
+        domains: identity.domains.clone(),
+        since: Some(since),
+        until: Some(until),
+        ..Default::default()
+    };
+    info!(
+        "{} is aggregating stats by {:?} from {} until {}",
+        identity.name, query.group_by, since, until
+    );
+    // Matches are only counted, not kept, so only the timeout applies
+    let budget = Budget::new(
+        usize::MAX,
+        StdDuration::from_secs(Config::global().limits.query_timeout),
+    );
+    let mdb = MAIL_DB.lock();
+    let matches = filter.find(&mdb, &budget)?;
+    let groups = aggregate(matches, query.group_by, window, identity.redactor.as_ref());
+    let total = groups.values().map(|g| g.total).sum();
+    Ok((
+        StatusCode::OK,
+        Extension(AuditResults(total)),
+        Json(StatsResponse {
+            since,
+            until,
+            bucket: window.bucket_seconds(),
+            total,
+            groups,
+            warming_up: HEALTH.is_warming_up(),
+        }),
+    )
+        .into_response())
+}
+
+/// Prometheus metrics of ingestion, the DB and queries.
+/// Only for identities that may see all domains, because metrics are labeled by domain.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses(
+        (status = 200, description = "Metrics in the Prometheus text format", body = String, content_type = "text/plain"),
+        (status = 403, description = "The identity is restricted to domains", body = ErrorResponse),
+    )
+)]
+pub async fn prometheus_metrics(
+    Extension(identity): Extension<Identity>,
+) -> Result<String, ApiError> {
+    if !identity.is_unrestricted() {
+        return Err(ApiError::forbidden(
+            "metrics are only available to tokens without domain restrictions",
+        ));
     }
+    metrics::gather().map_err(|why| ApiError::new(ErrorCode::Internal, format!("{why:#}")))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EraseQuery {
+    address: Option<String>,
+    /// Erases the domain and its subdomains
+    domain: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct EraseResponse {
+    /// The suppression rule that was added, a domain starts with @
+    suppressed: String,
+    /// Amount of mails that were removed from the DB
+    purged: usize,
+}
+
+/// Erases all mails to or from an address or domain, and suppresses it so it is never ingested again.
+/// Only for admin identities.
+#[utoipa::path(
+    post,
+    path = "/admin/erase",
+    params(EraseQuery),
+    responses(
+        (status = 200, description = "The address or domain was erased and suppressed", body = EraseResponse),
+        (status = 400, description = "Neither or both of address and domain, or an invalid one", body = ErrorResponse),
+        (status = 403, description = "The identity isn't an admin", body = ErrorResponse),
+    )
+)]
+pub async fn erase(
+    Extension(identity): Extension<Identity>,
+    query: ApiQuery<EraseQuery>,
+) -> Result<Response, ApiError> {
+    if let Some(forbidden) = admin_only(&identity, "erasing") {
+        return Err(forbidden);
+    }
+    let rule = match (&query.address, &query.domain) {
+        (Some(address), None) => match address.parse::<Rule>() {
+            Ok(Rule::Domain(d)) => {
+                return Err(ApiError::invalid_parameter(
+                    "address",
+                    format!("{d} is a domain, use the domain parameter"),
+                ))
+            }
+            Ok(rule) => rule,
+            Err(why) => return Err(ApiError::invalid_parameter("address", why)),
+        },
+        (None, Some(domain)) => format!("@{}", domain.trim_start_matches('@'))
+            .parse::<Rule>()
+            .map_err(|why| ApiError::invalid_parameter("domain", why))?,
+        (None, None) => {
+            return Err(ApiError::new(
+                ErrorCode::MissingParameter,
+                "either address or domain is required",
+            )
+            .with_details(json!({ "parameters": ["address", "domain"] })))
+        }
+        (Some(_), Some(_)) => {
+            return Err(ApiError::new(
+                ErrorCode::InvalidParameter,
+                "either address or domain is required, not both",
+            ))
+        }
+    };
+    // Suppress first, so mails that are ingested while purging are ignored
+    if let Err(why) = suppress::suppress(rule.clone()) {
+        error!("Failed to persist suppression of {rule}: {why:#}");
+        return Err(ApiError::new(
+            ErrorCode::Internal,
+            format!("failed to persist suppression: {why:#}"),
+        ));
+    }
+    let purged = MAIL_DB.purge(&rule);
+    info!("{} erased {rule}, purged {purged} mails", identity.name);
+    Ok((
+        StatusCode::OK,
+        Extension(AuditResults(purged)),
+        Json(EraseResponse {
+            suppressed: rule.to_string(),
+            purged,
+        }),
+    )
+        .into_response())
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportQuery {
+    kind: ImportKind,
+    /// Shown in the progress and logs, e.g. the name of the uploaded file
+    name: Option<String>,
+}
+
+fn admin_only(identity: &Identity, action: &str) -> Option<ApiError> {
+    (!identity.admin)
+        .then(|| ApiError::forbidden(format!("{action} requires an admin token or client")))
+}
+
+/// Stores an uploaded log or mailbox, plain or compressed, and imports it into the DB in the background.
+/// Responds once the upload is stored, with the import to follow the progress of. Only for admin identities.
+#[utoipa::path(
+    post,
+    path = "/admin/import",
+    params(ImportQuery),
+    request_body(content = String, description = "The file, plain, gzip or zstd compressed", content_type = "application/octet-stream"),
+    responses(
+        (status = 202, description = "The upload is stored and being imported", body = ImportReport),
+        (status = 400, description = "A parameter is invalid or the upload was interrupted", body = ErrorResponse),
+        (status = 403, description = "The identity isn't an admin", body = ErrorResponse),
+        (status = 413, description = "The upload is larger than max_upload_mb, details hold the import", body = ErrorResponse),
+    )
+)]
+pub async fn import(
+    Extension(identity): Extension<Identity>,
+    query: ApiQuery<ImportQuery>,
+    body: BodyStream,
+) -> Result<Response, ApiError> {
+    if let Some(forbidden) = admin_only(&identity, "importing") {
+        return Err(forbidden);
+    }
+    let name = query.name.as_deref().unwrap_or("upload");
+    let import = Import::start(name, query.kind, &identity.name);
+    let max_mb = Config::global().limits.max_upload_mb;
+    if let Err(why) = import.upload(body, max_mb).await {
+        let code = match why {
+            UploadError::TooLarge(_) => ErrorCode::PayloadTooLarge,
+            UploadError::Body(_) => ErrorCode::InvalidParameter,
+            UploadError::Io(_) => ErrorCode::Internal,
+        };
+        error!("Upload of {name} by {} failed: {why}", identity.name);
+        return Err(ApiError::new(code, why.to_string()).with_details(json!(import.report())));
+    }
+    import.spawn_parse();
+    Ok((StatusCode::ACCEPTED, Json(import.report())).into_response())
+}
+
+/// Progress of the recent imports. Only for admin identities.
+#[utoipa::path(
+    get,
+    path = "/admin/imports",
+    responses(
+        (status = 200, description = "The recent imports, oldest first", body = [ImportReport]),
+        (status = 403, description = "The identity isn't an admin", body = ErrorResponse),
+    )
+)]
+pub async fn imports(
+    Extension(identity): Extension<Identity>,
+) -> Result<Json<Vec<ImportReport>>, ApiError> {
+    if let Some(forbidden) = admin_only(&identity, "listing imports") {
+        return Err(forbidden);
+    }
+    Ok(Json(Import::all().iter().map(|i| i.report()).collect()))
+}
+
+/// Progress of an import. Only for admin identities.
+#[utoipa::path(
+    get,
+    path = "/admin/imports/{id}",
+    params(("id" = u64, Path, description = "ID of the import")),
+    responses(
+        (status = 200, description = "Progress of the import", body = ImportReport),
+        (status = 403, description = "The identity isn't an admin", body = ErrorResponse),
+        (status = 404, description = "No recent import has the ID", body = ErrorResponse),
+    )
+)]
+pub async fn import_progress(
+    Extension(identity): Extension<Identity>,
+    id: Result<Path<u64>, PathRejection>,
+) -> Result<Json<ImportReport>, ApiError> {
+    if let Some(forbidden) = admin_only(&identity, "following imports") {
+        return Err(forbidden);
+    }
+    let Path(id) = id.map_err(|why| ApiError::invalid_parameter("id", why.body_text()))?;
+    match Import::get(id) {
+        Some(import) => Ok(Json(import.report())),
+        None => Err(ApiError::new(
+            ErrorCode::NotFound,
+            format!("no import {id}"),
+        )),
+    }
+}
+
+/// Unknown paths get the same error body as the endpoints, so clients can tell them apart from empty results
+pub async fn not_found(uri: Uri) -> ApiError {
+    ApiError::new(
+        ErrorCode::NotFound,
+        format!("no endpoint at {}", uri.path()),
+    )
+}
+
+/// Liveness, the API is up as long as this responds
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    security(()),
+    responses((status = 200, description = "The API is up", body = String, content_type = "text/plain"))
+)]
+pub async fn healthz() -> impl IntoResponse {
+    (StatusCode::OK, "ok")
+}
+
+/// Readiness, the configured files are loaded and all tailers are running
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    security(()),
+    responses(
+        (status = 200, description = "Ready to serve complete results", body = Readiness),
+        (status = 503, description = "Files are still loading or a tailer isn't running", body = Readiness),
+    )
+)]
+pub async fn readyz() -> impl IntoResponse {
+    let readiness = HEALTH.readiness();
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(readiness))
 }
@@ -0,0 +1,137 @@
+use crate::query::TooBroad;
+use axum::async_trait;
+use axum::extract::{FromRequestParts, Query};
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fmt::Display;
+use std::ops::Deref;
+use utoipa::ToSchema;
+
+/// What went wrong, so clients can handle errors without parsing the message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// A required parameter is missing
+    MissingParameter,
+    /// A parameter can't be parsed or has an unsupported value
+    InvalidParameter,
+    /// The query matches too many mails or takes too long, narrow down the filters
+    QueryTooBroad,
+    /// The bearer token or client certificate is missing or unknown
+    Unauthorized,
+    /// The identity may not use the endpoint
+    Forbidden,
+    NotFound,
+    PayloadTooLarge,
+    RateLimited,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErrorCode::MissingParameter | ErrorCode::InvalidParameter => StatusCode::BAD_REQUEST,
+            ErrorCode::QueryTooBroad => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// An error of the API, responded with the status of its code
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+    /// Specifics of the error, e.g. the parameter that is invalid
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+}
+
+/// Body of every error response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorResponse {
+    pub error: ApiError,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        ApiError {
+            code,
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    pub fn missing_parameter(name: &str) -> Self {
+        ApiError::new(ErrorCode::MissingParameter, format!("missing {name}"))
+            .with_details(json!({ "parameter": name }))
+    }
+
+    /// Names the parameter in the details, the message is the full chain of the error
+    pub fn invalid_parameter(name: &str, why: impl Display) -> Self {
+        ApiError::new(ErrorCode::InvalidParameter, format!("{why:#}"))
+            .with_details(json!({ "parameter": name }))
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        ApiError::new(ErrorCode::Forbidden, message)
+    }
+}
+
+impl From<TooBroad> for ApiError {
+    fn from(why: TooBroad) -> Self {
+        let details = match why {
+            TooBroad::Matched(max) => json!({ "max_matched": max }),
+            TooBroad::Timeout(seconds) => json!({ "timeout_seconds": seconds }),
+        };
+        ApiError::new(ErrorCode::QueryTooBroad, why.to_string()).with_details(details)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.code.status(), Json(ErrorResponse { error: self })).into_response()
+    }
+}
+
+/// Extracts the query string like Query, but rejects it with an ApiError
+pub struct ApiQuery<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for ApiQuery<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        match Query::<T>::from_request_parts(parts, state).await {
+            Ok(Query(query)) => Ok(ApiQuery(query)),
+            Err(why) => Err(ApiError::new(ErrorCode::InvalidParameter, why.body_text())),
+        }
+    }
+}
+
+impl<T> Deref for ApiQuery<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
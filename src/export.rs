@@ -0,0 +1,146 @@
+use crate::auth::Identity;
+use crate::endpoints::budget;
+use crate::mail::{Mail, MAIL_DB};
+use crate::query::{paginate, Cursor, MailFilter, SortOrder, MAX_LIMIT};
+use axum::body::StreamBody;
+use axum::http::header::{HeaderName, CONTENT_TYPE};
+use axum::http::HeaderValue;
+use axum::response::{IntoResponse, Response};
+use log::error;
+use serde::Deserialize;
+use std::io;
+use utoipa::ToSchema;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Results keyed by address, with totals and the next cursor in the body
+    #[default]
+    Json,
+    /// One JSON object per line, per delivery attempt
+    Ndjson,
+    /// One row per delivery attempt, with a header row
+    Csv,
+}
+
+/// Rows are read from the DB in pages of this size, so it is only locked for one page at a time
+const PAGE_SIZE: usize = MAX_LIMIT;
+
+const CSV_HEADER: &str = "id,timestamp,from,to,status,relay,dsn,subject,line\n";
+
+/// Totals and the next cursor are sent as headers, because the body only holds rows
+pub static TOTAL_COUNT: HeaderName = HeaderName::from_static("x-total-count");
+pub static NEXT_CURSOR: HeaderName = HeaderName::from_static("x-next-cursor");
+
+fn serialize_row(mail: &Mail, format: Format) -> io::Result<Vec<u8>> {
+    match format {
+        Format::Csv => {
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(vec![]);
+            writer.serialize(mail).map_err(io::Error::other)?;
+            writer.into_inner().map_err(|why| why.into_error())
+        }
+        _ => {
+            let mut row = serde_json::to_vec(mail)?;
+            row.push(b'\n');
+            Ok(row)
+        }
+    }
+}
+
+/// The rows of an export, read page by page, each page continuing after the previous one
+pub struct Rows {
+    filter: MailFilter,
+    identity: Identity,
+    order: SortOrder,
+    cursor: Option<Cursor>,
+    /// Rows that are still to be read
+    remaining: usize,
+    format: Format,
+    page: std::vec::IntoIter<Mail>,
+}
+
+impl Rows {
+    /// Reads at most `limit` rows matching the filter, starting after `cursor` if one is given
+    pub fn new(
+        filter: MailFilter,
+        identity: Identity,
+        order: SortOrder,
+        cursor: Option<Cursor>,
+        limit: usize,
+        format: Format,
+    ) -> Self {
+        Rows {
+            filter,
+            identity,
+            order,
+            cursor,
+            remaining: limit,
+            format,
+            page: Vec::new().into_iter(),
+        }
+    }
+
+    /// Locks the DB again and reads the next page, redacted for the identity
+    fn read_page(&mut self) -> anyhow::Result<()> {
+        let mdb = MAIL_DB.lock();
+        let matches = self.filter.find(&mdb, &budget())?;
+        let page = paginate(
+            matches,
+            self.order,
+            self.cursor.as_ref(),
+            self.remaining.min(PAGE_SIZE),
+        );
+        if page.next_cursor.is_none() {
+            self.remaining = 0;
+        } else {
+            self.remaining -= page.mails.len();
+        }
+        self.cursor = page.mails.last().map(|mail| Cursor::from(*mail));
+        self.page = page
+            .mails
+            .into_iter()
+            .map(|mail| self.identity.redact(mail.clone()))
+            .collect::<Vec<_>>()
+            .into_iter();
+        Ok(())
+    }
+}
+
+impl Iterator for Rows {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.page.len() == 0 && self.remaining > 0 {
+            // The response has started already, so the only way to fail is to abort it
+            if let Err(why) = self.read_page() {
+                error!("Aborted export: {:#}", why);
+                self.remaining = 0;
+                return Some(Err(io::Error::other(why)));
+            }
+        }
+        self.page
+            .next()
+            .map(|mail| serialize_row(&mail, self.format))
+    }
+}
+
+/// Streams the rows one at a time, with the total and the next cursor of the whole export as headers
+pub fn export(rows: Rows, total: usize, next_cursor: Option<Cursor>) -> Response {
+    let format = rows.format;
+    let content_type = match format {
+        Format::Csv => "text/csv; charset=utf-8",
+        _ => "application/x-ndjson",
+    };
+    let header = (format == Format::Csv).then(|| Ok(CSV_HEADER.as_bytes().to_vec()));
+    let body = StreamBody::new(tokio_stream::iter(header.into_iter().chain(rows)));
+    let mut response = body.into_response();
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    headers.insert(TOTAL_COUNT.clone(), HeaderValue::from(total));
+    if let Some(cursor) = next_cursor.and_then(|c| HeaderValue::from_str(&c.to_string()).ok()) {
+        headers.insert(NEXT_CURSOR.clone(), cursor);
+    }
+    response
+}
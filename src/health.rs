@@ -0,0 +1,163 @@
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use utoipa::ToSchema;
+
+/// Loading progress of the configured files and liveness of the tailers
+pub(crate) static HEALTH: Lazy<Health> = Lazy::new(Health::new);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileState {
+    Pending,
+    Loaded,
+    Failed,
+}
+
+#[derive(Debug)]
+pub struct Health {
+    files: Mutex<Vec<(String, FileState)>>,
+    initializing: AtomicBool,
+    tailers: Mutex<BTreeMap<String, bool>>,
+    tasks: Mutex<BTreeMap<String, TaskHealth>>,
+}
+
+/// Failures of a supervised task
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct TaskHealth {
+    pub restarts: u64,
+    pub last_error: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Readiness {
+    pub ready: bool,
+    /// Percentage of configured files that have been processed, loaded or failed
+    pub progress: f64,
+    pub loaded: Vec<String>,
+    pub pending: Vec<String>,
+    pub failed: Vec<String>,
+    /// Whether the task tailing each file is running
+    pub tailers: BTreeMap<String, bool>,
+    /// Supervised tasks that failed and were restarted
+    pub tasks: BTreeMap<String, TaskHealth>,
+}
+
+/// Marks a tailer as alive for as long as it is held
+pub struct TailerGuard(String);
+
+impl Drop for TailerGuard {
+    fn drop(&mut self) {
+        // Tailers that were stopped because their source was removed aren't reported anymore
+        if let Some(alive) = HEALTH.tailers.lock().get_mut(&self.0) {
+            *alive = false;
+        }
+    }
+}
+
+impl Health {
+    /// The DB is warming up until loading has finished
+    fn new() -> Self {
+        Health {
+            files: Mutex::new(vec![]),
+            initializing: AtomicBool::new(true),
+            tailers: Mutex::new(BTreeMap::new()),
+            tasks: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn set_warming_up(&self, warming_up: bool) {
+        self.initializing.store(warming_up, Ordering::Relaxed);
+    }
+
+    /// Registers the files that are about to be loaded as pending
+    pub fn start_loading(&self, files: &[PathBuf]) {
+        let mut state = self.files.lock();
+        for file in files {
+            let file = file.display().to_string();
+            match state.iter_mut().find(|(f, _)| *f == file) {
+                Some((_, s)) => *s = FileState::Pending,
+                None => state.push((file, FileState::Pending)),
+            }
+        }
+    }
+
+    /// Forgets the files that are no longer configured
+    pub fn retain_files(&self, files: &[PathBuf]) {
+        let files: Vec<String> = files.iter().map(|f| f.display().to_string()).collect();
+        self.files.lock().retain(|(f, _)| files.contains(f));
+    }
+
+    pub fn set_file_state(&self, file: &Path, new_state: FileState) {
+        let file = file.display().to_string();
+        if let Some((_, state)) = self.files.lock().iter_mut().find(|(f, _)| *f == file) {
+            *state = new_state;
+        }
+    }
+
+    /// Marks loading of the files as done, those that were not processed are failed
+    pub fn finish_loading(&self, files: &[PathBuf]) {
+        let files: Vec<String> = files.iter().map(|f| f.display().to_string()).collect();
+        for (file, state) in self.files.lock().iter_mut() {
+            if *state == FileState::Pending && files.contains(file) {
+                *state = FileState::Failed;
+            }
+        }
+    }
+
+    pub fn is_warming_up(&self) -> bool {
+        self.initializing.load(Ordering::Relaxed)
+    }
+
+    pub fn tailer_alive(&self, file: &Path) -> TailerGuard {
+        let file = file.display().to_string();
+        self.tailers.lock().insert(file.clone(), true);
+        TailerGuard(file)
+    }
+
+    /// Stops reporting a tailer whose source was removed
+    pub fn remove_tailer(&self, file: &Path) {
+        self.tailers.lock().remove(&file.display().to_string());
+    }
+
+    pub fn task_failed(&self, task: &str, why: &anyhow::Error) {
+        let mut tasks = self.tasks.lock();
+        let task = tasks.entry(task.to_string()).or_default();
+        task.restarts += 1;
+        task.last_error = format!("{why:#}");
+    }
+
+    pub fn readiness(&self) -> Readiness {
+        let files = self.files.lock();
+        let with_state = |wanted: FileState| {
+            files
+                .iter()
+                .filter(|(_, state)| *state == wanted)
+                .map(|(file, _)| file.clone())
+                .collect::<Vec<_>>()
+        };
+        let (loaded, pending, failed) = (
+            with_state(FileState::Loaded),
+            with_state(FileState::Pending),
+            with_state(FileState::Failed),
+        );
+        let progress = if files.is_empty() {
+            100.0
+        } else {
+            (files.len() - pending.len()) as f64 / files.len() as f64 * 100.0
+        };
+        let tailers = self.tailers.lock().clone();
+        Readiness {
+            ready: !self.is_warming_up() && tailers.values().all(|alive| *alive),
+            progress,
+            loaded,
+            pending,
+            failed,
+            tailers,
+            tasks: self.tasks.lock().clone(),
+        }
+    }
+}
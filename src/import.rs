@@ -0,0 +1,313 @@
+use crate::mail::{parse_mail_subjects, parse_mails, FileLines, MAIL_DB};
+use axum::extract::BodyStream;
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use log::{error, info};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Read, Seek};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::io::AsyncWriteExt;
+use utoipa::ToSchema;
+
+/// Imports of uploaded files by ID, the most recent ones are kept to report on
+static IMPORTS: Lazy<Mutex<BTreeMap<u64, Arc<Import>>>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+const KEEP_IMPORTS: usize = 100;
+
+/// Magic bytes of the compressed formats that FileLines can read
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportKind {
+    /// A postfix log, like the configured log files
+    Log,
+    /// A mailbox to take subjects from, like the configured mail files
+    Mail,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportState {
+    Uploading,
+    Parsing,
+    Done,
+    Failed,
+}
+
+#[derive(Error, Debug)]
+pub enum UploadError {
+    #[error("upload is larger than {0} MB")]
+    TooLarge(u64),
+    #[error("upload was interrupted: {0}")]
+    Body(#[from] axum::Error),
+    #[error("failed to store upload: {0}")]
+    Io(#[from] io::Error),
+}
+
+#[derive(Debug)]
+struct ImportStatus {
+    state: ImportState,
+    /// Mails inserted from a log, or subjects updated from a mailbox
+    inserted: usize,
+    finished: Option<DateTime<Utc>>,
+    error: Option<String>,
+}
+
+/// An uploaded file that is stored, then parsed into the DB in the background
+#[derive(Debug)]
+pub struct Import {
+    id: u64,
+    name: String,
+    kind: ImportKind,
+    identity: String,
+    started: DateTime<Utc>,
+    /// Bytes of the upload as stored, compressed if it is
+    uploaded: AtomicU64,
+    /// Bytes of the stored upload that were parsed
+    parsed: AtomicU64,
+    status: Mutex<ImportStatus>,
+}
+
+/// Progress of an import, as reported to clients
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ImportReport {
+    id: u64,
+    name: String,
+    kind: ImportKind,
+    identity: String,
+    started: DateTime<Utc>,
+    state: ImportState,
+    uploaded_bytes: u64,
+    parsed_bytes: u64,
+    /// Percentage of the upload that was parsed
+    progress: f64,
+    inserted: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    finished: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Counts the bytes read from the stored upload, for the progress
+struct CountingReader<R> {
+    inner: R,
+    import: Arc<Import>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.import.parsed.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+/// Fails the import if it is dropped before being disarmed, e.g. when the client disconnects
+/// and the upload is dropped with its handler, so no import stays unfinished with its file left behind
+struct FinishGuard<'a> {
+    import: &'a Import,
+    interrupted: &'static str,
+    armed: bool,
+}
+
+impl<'a> FinishGuard<'a> {
+    fn new(import: &'a Import, interrupted: &'static str) -> Self {
+        FinishGuard {
+            import,
+            interrupted,
+            armed: true,
+        }
+    }
+
+    fn disarm(mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for FinishGuard<'_> {
+    fn drop(&mut self) {
+        if self.armed {
+            error!(
+                "Failed to import {}: {}",
+                self.import.name, self.interrupted
+            );
+            self.import.finish(Err(self.interrupted.to_string()));
+        }
+    }
+}
+
+impl Import {
+    /// Registers a new import, forgetting the oldest finished ones
+    pub fn start(name: &str, kind: ImportKind, identity: &str) -> Arc<Import> {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let import = Arc::new(Import {
+            id,
+            name: name.to_string(),
+            kind,
+            identity: identity.to_string(),
+            started: Utc::now(),
+            uploaded: AtomicU64::new(0),
+            parsed: AtomicU64::new(0),
+            status: Mutex::new(ImportStatus {
+                state: ImportState::Uploading,
+                inserted: 0,
+                finished: None,
+                error: None,
+            }),
+        });
+        let mut imports = IMPORTS.lock();
+        imports.insert(id, import.clone());
+        while imports.len() > KEEP_IMPORTS {
+            let finished = imports
+                .iter()
+                .find(|(_, i)| i.status.lock().finished.is_some())
+                .map(|(id, _)| *id);
+            match finished {
+                Some(id) => imports.remove(&id),
+                None => break,
+            };
+        }
+        import
+    }
+
+    pub fn get(id: u64) -> Option<Arc<Import>> {
+        IMPORTS.lock().get(&id).cloned()
+    }
+
+    /// All imports that are kept, oldest first
+    pub fn all() -> Vec<Arc<Import>> {
+        IMPORTS.lock().values().cloned().collect()
+    }
+
+    /// Where the upload is stored until it is parsed
+    fn path(&self) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "linux-mail-db-import-{}-{}",
+            std::process::id(),
+            self.id
+        ))
+    }
+
+    fn finish(&self, result: Result<usize, String>) {
+        let _ = std::fs::remove_file(self.path());
+        let mut status = self.status.lock();
+        match result {
+            Ok(inserted) => {
+                status.state = ImportState::Done;
+                status.inserted = inserted;
+            }
+            Err(why) => {
+                status.state = ImportState::Failed;
+                status.error = Some(why);
+            }
+        }
+        status.finished = Some(Utc::now());
+    }
+
+    /// Stores the uploaded body, so it can be parsed like any other file
+    pub async fn upload(&self, mut body: BodyStream, max_mb: u64) -> Result<(), UploadError> {
+        let guard = FinishGuard::new(self, "upload was interrupted");
+        let res = async {
+            let mut file = tokio::fs::File::create(self.path()).await?;
+            while let Some(chunk) = body.next().await {
+                let chunk = chunk?;
+                let uploaded = self
+                    .uploaded
+                    .fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                if uploaded + chunk.len() as u64 > max_mb * 1024 * 1024 {
+                    return Err(UploadError::TooLarge(max_mb));
+                }
+                file.write_all(&chunk).await?;
+            }
+            file.flush().await?;
+            Ok(())
+        }
+        .await;
+        guard.disarm();
+        if let Err(why) = &res {
+            self.finish(Err(why.to_string()));
+        }
+        res
+    }
+
+    /// Decompresses the stored upload by its magic bytes and parses it into the DB
+    fn parse(self: &Arc<Self>) -> anyhow::Result<usize> {
+        let mut file = File::open(self.path())?;
+        let mut magic = [0; 4];
+        let n = file.read(&mut magic)?;
+        file.rewind()?;
+        let extension = match &magic[..n] {
+            m if m.starts_with(GZIP_MAGIC) => "gz",
+            m if m.starts_with(ZSTD_MAGIC) => "zst",
+            _ => "",
+        };
+        let reader = CountingReader {
+            inner: file,
+            import: self.clone(),
+        };
+        let lines = FileLines::decompress(reader, extension)?;
+        // One source for all imports, so the metrics aren't labeled by arbitrary names
+        Ok(match self.kind {
+            ImportKind::Log => MAIL_DB.insert_mail_log(parse_mails(lines, "import")?),
+            ImportKind::Mail => MAIL_DB.update_mail_subjects(parse_mail_subjects(lines, "import")?),
+        } as usize)
+    }
+
+    /// Parses the stored upload in the background
+    pub fn spawn_parse(self: &Arc<Self>) {
+        self.status.lock().state = ImportState::Parsing;
+        let import = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let guard = FinishGuard::new(&import, "parsing was interrupted");
+            info!(
+                "Importing {} uploaded by {}...",
+                import.name, import.identity
+            );
+            match import.parse() {
+                Ok(inserted) => {
+                    info!("Imported {inserted} mails or subjects from {}", import.name);
+                    import.finish(Ok(inserted));
+                }
+                Err(why) => {
+                    error!("Failed to import {}: {why:#}", import.name);
+                    import.finish(Err(format!("{why:#}")));
+                }
+            }
+            guard.disarm();
+        });
+    }
+
+    pub fn report(&self) -> ImportReport {
+        let status = self.status.lock();
+        let uploaded_bytes = self.uploaded.load(Ordering::Relaxed);
+        let parsed_bytes = self.parsed.load(Ordering::Relaxed);
+        let progress = match status.state {
+            ImportState::Done => 100.0,
+            _ if uploaded_bytes == 0 => 0.0,
+            _ => parsed_bytes as f64 / uploaded_bytes as f64 * 100.0,
+        };
+        ImportReport {
+            id: self.id,
+            name: self.name.clone(),
+            kind: self.kind,
+            identity: self.identity.clone(),
+            started: self.started,
+            state: status.state,
+            uploaded_bytes,
+            parsed_bytes,
+            progress,
+            inserted: status.inserted,
+            finished: status.finished,
+            error: status.error.clone(),
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use rustc_hash::{FxHashMap, FxHashSet};
+
+/// Inverted index from the tokens in log lines to the email addresses that have mails
+/// containing them. Addresses are numbered to keep the postings small.
+#[derive(Debug, Default)]
+pub struct TokenIndex {
+    address_numbers: FxHashMap<String, u32>,
+    addresses: Vec<String>,
+    postings: FxHashMap<String, FxHashSet<u32>>,
+}
+
+impl TokenIndex {
+    /// Adds the tokens of a line to the postings of the given address
+    pub fn insert(&mut self, address: &str, line: &str) {
+        let number = match self.address_numbers.get(address) {
+            Some(n) => *n,
+            None => {
+                let n = self.addresses.len() as u32;
+                self.addresses.push(address.to_string());
+                self.address_numbers.insert(address.to_string(), n);
+                n
+            }
+        };
+        for token in tokenize(line) {
+            self.postings.entry(token).or_default().insert(number);
+        }
+    }
+
+    /// Returns the addresses that have mails containing all given tokens.
+    /// A single mail of the address does not necessarily contain all of them.
+    pub fn addresses(&self, tokens: &[String]) -> Vec<&str> {
+        let mut postings = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            match self.postings.get(token) {
+                Some(p) => postings.push(p),
+                None => return vec![],
+            }
+        }
+        postings.sort_unstable_by_key(|p| p.len());
+        let Some((smallest, rest)) = postings.split_first() else {
+            return vec![];
+        };
+        smallest
+            .iter()
+            .filter(|n| rest.iter().all(|p| p.contains(n)))
+            .map(|n| self.addresses[*n as usize].as_str())
+            .collect()
+    }
+}
+
+/// Splits text into lowercase tokens of letters, digits and the characters that
+/// occur in addresses, hostnames and status codes, e.g. "5.7.1" or "1.2.3.4".
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric() && !matches!(c, '.' | '@' | '-' | '_' | '+'))
+        .map(|t| t.trim_matches(|c| matches!(c, '.' | '-')))
+        .filter(|t| !t.is_empty())
+        .map(str::to_lowercase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(text: &str) -> Vec<String> {
+        tokenize(text).collect()
+    }
+
+    #[test]
+    fn tokenize_keeps_addresses_and_codes() {
+        assert_eq!(
+            tokens("to=<Bob@Example.com>, relay=mx.example.com[1.2.3.4]:25, dsn=5.7.1."),
+            [
+                "to",
+                "bob@example.com",
+                "relay",
+                "mx.example.com",
+                "1.2.3.4",
+                "25",
+                "dsn",
+                "5.7.1"
+            ]
+        );
+        assert_eq!(tokens("-- ... (deferred)"), ["deferred"]);
+        assert!(tokens("").is_empty());
+    }
+
+    #[test]
+    fn addresses_contain_all_tokens() {
+        let mut index = TokenIndex::default();
+        index.insert("a@example.com", "status=sent (250 ok)");
+        index.insert("b@example.com", "status=bounced (550 blocked)");
+        index.insert("b@example.com", "status=sent (250 ok)");
+        let mut found = index.addresses(&tokens("250 SENT"));
+        found.sort_unstable();
+        assert_eq!(found, ["a@example.com", "b@example.com"]);
+        // The tokens may be spread over several mails of an address
+        assert_eq!(index.addresses(&tokens("250 blocked")), ["b@example.com"]);
+        assert!(index.addresses(&tokens("250 unknown")).is_empty());
+        assert!(index.addresses(&[]).is_empty());
+    }
+}
@@ -0,0 +1,121 @@
+use crate::auth::Identity;
+use crate::config::{Config, ConfigLimits};
+use crate::error::{ApiError, ErrorCode};
+use crate::tls::ClientCert;
+use axum::extract::ConnectInfo;
+use axum::http::header::RETRY_AFTER;
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use log::warn;
+use once_cell::sync::Lazy;
+use parking_lot::{Mutex, RwLock};
+use rustc_hash::FxHashMap;
+use serde_json::json;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Rate limiter of the query endpoints, if configured
+static RATE_LIMITER: Lazy<RwLock<Option<Arc<RateLimiter>>>> =
+    Lazy::new(|| RwLock::new(RateLimiter::from_config(&Config::global().limits)));
+
+/// Replaces the rate limiter if the rate or burst changed, which resets all buckets
+pub fn reload(old: &ConfigLimits, new: &ConfigLimits) {
+    if (old.requests_per_minute, old.burst) != (new.requests_per_minute, new.burst) {
+        *RATE_LIMITER.write() = RateLimiter::from_config(new);
+    }
+}
+
+/// Buckets are only cleaned up once there are this many
+const MAX_BUCKETS: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket per client: every request takes a token, tokens refill at a constant rate up to the burst
+struct RateLimiter {
+    per_second: f64,
+    burst: f64,
+    buckets: Mutex<FxHashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    fn from_config(limits: &ConfigLimits) -> Option<Arc<Self>> {
+        limits.requests_per_minute.map(|rpm| {
+            Arc::new(RateLimiter::new(
+                rpm as f64 / 60.0,
+                limits.burst.unwrap_or(rpm).max(1) as f64,
+            ))
+        })
+    }
+
+    fn new(per_second: f64, burst: f64) -> Self {
+        RateLimiter {
+            per_second,
+            burst,
+            buckets: Mutex::new(FxHashMap::default()),
+        }
+    }
+
+    /// Takes a token of the client, or returns how long until the next token is available
+    fn acquire(&self, client: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock();
+        if buckets.len() >= MAX_BUCKETS {
+            // Full buckets are the same as new ones
+            buckets.retain(|_, b| {
+                b.tokens + now.duration_since(b.updated).as_secs_f64() * self.per_second
+                    < self.burst
+            });
+        }
+        let bucket = buckets.entry(client.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let refilled = now.duration_since(bucket.updated).as_secs_f64() * self.per_second;
+        bucket.tokens = (bucket.tokens + refilled).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            // A rate of 0 is rejected by the config check, but must not panic either
+            let wait = Duration::try_from_secs_f64((1.0 - bucket.tokens) / self.per_second);
+            return Err(wait.unwrap_or(Duration::from_secs(60)));
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+}
+
+/// Limits requests per authenticated identity, or per client IP for anonymous requests.
+/// Client certificates identify the client even without an `auth` section.
+/// Has to run after authentication, so the identity is known.
+pub async fn rate_limit<B>(req: Request<B>, next: Next<B>) -> Response {
+    let Some(limiter) = RATE_LIMITER.read().clone() else {
+        return next.run(req).await;
+    };
+    let authenticated = Config::global().auth.is_some()
+        || matches!(req.extensions().get::<Option<ClientCert>>(), Some(Some(_)));
+    let client = match req.extensions().get::<Identity>() {
+        Some(identity) if authenticated => format!("identity {}", identity.name),
+        _ => req
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map_or_else(
+                || String::from("unknown"),
+                |ConnectInfo(addr)| format!("ip {}", addr.ip()),
+            ),
+    };
+    if let Err(retry_after) = limiter.acquire(&client) {
+        let retry_after = retry_after.as_secs() + 1;
+        warn!("Rate limited {client} on {}", req.uri().path());
+        let error = ApiError::new(
+            ErrorCode::RateLimited,
+            format!("rate limit exceeded, retry in {retry_after}s"),
+        )
+        .with_details(json!({ "retry_after_seconds": retry_after }));
+        return ([(RETRY_AFTER, retry_after.to_string())], error).into_response();
+    }
+    next.run(req).await
+}
@@ -1,38 +1,241 @@
+use crate::health::{FileState, HEALTH};
+use crate::index::TokenIndex;
+use crate::metrics;
+use crate::suppress::{self, Rule};
 use crate::{Config, FileTail};
 use anyhow::{bail, Context, Result};
 use bytelines::ByteLinesReader;
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, TimeZone, Utc};
 use flate2::read::GzDecoder;
 use log::{debug, error, info, warn};
 use once_cell::sync::Lazy;
 use parking_lot::{Mutex, MutexGuard};
 use rustc_hash::FxHashMap;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufReader, Read};
 use std::path::PathBuf;
 use std::time::Duration;
+use tokio::sync::broadcast;
 use tokio::{task, time};
+use utoipa::ToSchema;
 
-pub(crate) static MAIL_DB: Lazy<MailDB> = Lazy::new(|| MailDB::new());
+pub(crate) static MAIL_DB: Lazy<MailDB> = Lazy::new(MailDB::new);
 
+/// Changes to MAIL_DB, for clients that stream new deliveries
+pub(crate) static MAIL_EVENTS: Lazy<broadcast::Sender<MailEvent>> =
+    Lazy::new(|| broadcast::channel(1024).0);
+
+#[derive(Debug, Clone, Copy)]
+pub enum MailEventKind {
+    /// A recipient of a mail that wasn't in the DB yet
+    New,
+    /// Another delivery attempt for a recipient of a mail that was already in the DB
+    StatusUpdate,
+    /// A subject or sender was attached to a mail
+    Updated,
+}
+
+impl MailEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MailEventKind::New => "new",
+            MailEventKind::StatusUpdate => "status_update",
+            MailEventKind::Updated => "updated",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MailEvent {
+    pub kind: MailEventKind,
+    pub mail: Mail,
+}
+
+/// Sends an event if anyone is listening, so mails aren't cloned needlessly while loading
+fn send_event(kind: MailEventKind, mail: &Mail) {
+    if MAIL_EVENTS.receiver_count() > 0 {
+        let _ = MAIL_EVENTS.send(MailEvent {
+            kind,
+            mail: mail.clone(),
+        });
+    }
+}
+
+/// Mails by recipient address. Envelopes are kept by mail ID, because postfix/qmgr
+/// logs the sender on a different line than postfix/smtp logs the recipients.
+/// Lock order is always `mails` first, then `envelopes`.
 #[derive(Debug)]
-pub struct MailDB(Mutex<FxHashMap<String, Vec<Mail>>>);
+pub struct MailDB {
+    mails: Mutex<Mails>,
+    envelopes: Mutex<Envelopes>,
+}
+
+/// Mails by recipient address, along with a full-text index over their log lines
+#[derive(Debug, Default)]
+pub struct Mails {
+    by_address: FxHashMap<String, Vec<Mail>>,
+    index: TokenIndex,
+    next_seq: u64,
+}
+
+impl Mails {
+    pub fn by_address(&self) -> &FxHashMap<String, Vec<Mail>> {
+        &self.by_address
+    }
+
+    pub fn index(&self) -> &TokenIndex {
+        &self.index
+    }
+
+    fn get_mut(&mut self, address: &str) -> Option<&mut Vec<Mail>> {
+        self.by_address.get_mut(address)
+    }
+
+    /// Indexes the remaining mails from scratch, so no tokens of removed mails are left behind
+    fn rebuild_index(&mut self) {
+        self.index = TokenIndex::default();
+        for mail in self.by_address.values().flatten() {
+            if let Some(line) = &mail.line {
+                self.index.insert(&mail.to, line);
+            }
+        }
+    }
+
+    fn push(&mut self, mut mail: Mail) {
+        mail.seq = self.next_seq;
+        self.next_seq += 1;
+        if let Some(line) = &mail.line {
+            self.index.insert(&mail.to, line);
+        }
+        self.by_address
+            .entry(mail.to.clone())
+            .or_default()
+            .push(mail);
+    }
+}
+
+/// How long an envelope is kept after its last line, longer than postfix keeps
+/// a mail in the queue (maximal_queue_lifetime is 5 days by default)
+const ENVELOPE_RETENTION: i64 = 7 * 24 * 60 * 60;
+/// How often envelopes are aged out, in seconds of log time
+const ENVELOPE_PRUNE_INTERVAL: i64 = 60 * 60;
+
+#[derive(Debug, Default)]
+struct Envelope {
+    /// Not kept for suppressed senders, they are only marked
+    from: Option<String>,
+    suppressed: bool,
+    recipients: Vec<String>,
+    /// Timestamp of the newest line of this mail
+    last_seen: i64,
+}
+
+/// Envelopes by mail ID. They are aged out by the timestamps of the log lines
+/// rather than the clock, so loading old files doesn't keep them forever either.
+#[derive(Debug, Default)]
+struct Envelopes {
+    by_id: FxHashMap<String, Envelope>,
+    /// Timestamp of the newest line seen
+    newest: i64,
+    next_prune: i64,
+}
+
+impl Envelopes {
+    /// Returns the envelope of the given mail ID, seen in a line with the given timestamp.
+    /// Lines without a timestamp count as the newest line.
+    fn touch(&mut self, id: &str, timestamp: Option<DateTime<Utc>>) -> &mut Envelope {
+        let seen = timestamp.map_or(self.newest, |t| t.timestamp());
+        self.newest = self.newest.max(seen);
+        let envelope = self.by_id.entry(id.to_string()).or_default();
+        envelope.last_seen = envelope.last_seen.max(seen);
+        envelope
+    }
+
+    /// Drops the envelopes of mails that postfix can't deliver anymore
+    fn prune(&mut self) {
+        if self.newest < self.next_prune {
+            return;
+        }
+        let oldest = self.newest.saturating_sub(ENVELOPE_RETENTION);
+        self.by_id
+            .retain(|_, envelope| envelope.last_seen >= oldest);
+        self.next_prune = self.newest.saturating_add(ENVELOPE_PRUNE_INTERVAL);
+    }
+}
 
 impl MailDB {
     pub fn new() -> Self {
-        MailDB(Mutex::new(FxHashMap::default()))
+        MailDB {
+            mails: Mutex::new(Mails::default()),
+            envelopes: Mutex::new(Envelopes::default()),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, Mails> {
+        self.mails.lock()
     }
 
-    pub fn lock(&self) -> MutexGuard<FxHashMap<String, Vec<Mail>>> {
-        self.0.lock()
+    /// Stores the envelope senders and attaches them to the recipients
+    /// of the same mail ID that are already in the DB
+    pub fn insert_senders(&self, senders: Vec<Sender>) -> i32 {
+        let mut mails = self.mails.lock();
+        let mut envelopes = self.envelopes.lock();
+        let mut updates = 0;
+        let mut purged = false;
+        for sender in senders {
+            let envelope = envelopes.touch(&sender.id, sender.timestamp);
+            let suppressed = suppress::is_suppressed(&sender.from);
+            // postfix reuses queue IDs, so another sender means another mail
+            let reused = match &envelope.from {
+                Some(from) => *from != sender.from,
+                None => envelope.suppressed && !suppressed,
+            };
+            if reused {
+                *envelope = Envelope {
+                    last_seen: envelope.last_seen,
+                    ..Default::default()
+                };
+            }
+            if suppressed {
+                // Attempts that were inserted before their suppressed sender was known,
+                // not those of an earlier mail with the same ID and a known sender
+                for recipient in &envelope.recipients {
+                    if let Some(db_mails) = mails.get_mut(recipient) {
+                        db_mails.retain(|m| m.id != sender.id || m.from.is_some());
+                        purged = true;
+                    }
+                }
+                envelope.recipients.clear();
+                envelope.suppressed = true;
+                continue;
+            }
+            for recipient in &envelope.recipients {
+                let db_mails = mails.get_mut(recipient).into_iter().flatten();
+                for db_mail in db_mails.filter(|m| m.id == sender.id && m.from.is_none()) {
+                    db_mail.from = Some(sender.from.clone());
+                    send_event(MailEventKind::Updated, db_mail);
+                    updates += 1;
+                }
+            }
+            envelope.from = Some(sender.from);
+        }
+        if purged {
+            mails.by_address.retain(|_, db_mails| !db_mails.is_empty());
+            mails.rebuild_index();
+        }
+        updates
     }
 
     /// Loop through local MAIL_DB and find corresponding emails that have no subject
-    /// and update the subject accordingly
+    /// and update the subject of all their delivery attempts accordingly
     pub fn update_mail_subjects(&self, new_mails: Vec<Mail>) -> i32 {
-        let mut hashmap_locked = self.0.lock();
-        let mut updates = 0;
+        let mut hashmap_locked = self.mails.lock();
+        let (mut updates, mut orphans) = (0, 0);
         for new_mail in new_mails {
+            if suppress::is_suppressed(&new_mail.to) {
+                continue;
+            }
             let entry = hashmap_locked.get_mut(&new_mail.to);
             match entry {
                 None => {
@@ -40,66 +243,213 @@ impl MailDB {
                         "no email address found for inserting mail subjects: {}",
                         &new_mail.to
                     );
+                    orphans += 1;
                 }
                 Some(db_mails) => {
+                    if !db_mails.iter().any(|m| m.id == new_mail.id) {
+                        orphans += 1;
+                    }
+                    let mut updated = false;
                     for db_mail in db_mails {
                         if db_mail.subject.is_none() && db_mail.id == new_mail.id {
-                            db_mail.subject = new_mail.subject;
-                            updates += 1;
-                            break;
+                            db_mail.subject = new_mail.subject.clone();
+                            send_event(MailEventKind::Updated, db_mail);
+                            updated = true;
                         }
                     }
+                    if updated {
+                        updates += 1;
+                    }
                 }
             }
         }
+        metrics::SUBJECTS
+            .with_label_values(&["matched"])
+            .inc_by(updates as u64);
+        metrics::SUBJECTS
+            .with_label_values(&["orphaned"])
+            .inc_by(orphans);
         updates
     }
 
-    /// inserts given mails into local MAIL_DB
+    /// inserts given mails into local MAIL_DB, keeping every delivery attempt of a mail
     pub fn insert_mails(&self, new_mails: Vec<Mail>) -> i32 {
         let mut updates = 0;
-        let mut lock = self.0.lock();
-        for new_mail in new_mails {
-            // Only update mail in MAIL_DB if a mail with an identical ID does not already exist
-            let db_mail_entry = lock.entry(new_mail.to.clone()).or_insert(Vec::new());
-            if !db_mail_entry.iter().any(|m| m.id == new_mail.id) {
-                db_mail_entry.push(new_mail.clone());
-                updates += 1;
+        let mut lock = self.mails.lock();
+        let mut envelopes = self.envelopes.lock();
+        for mut new_mail in new_mails {
+            if suppress::is_suppressed(&new_mail.to) {
+                continue;
             }
+            let attempts = lock
+                .by_address
+                .get(&new_mail.to)
+                .map(|db_mails| {
+                    db_mails
+                        .iter()
+                        .filter(|m| m.id == new_mail.id)
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+            // Only update mail in MAIL_DB if this attempt was not inserted before, e.g. when reloading files
+            if attempts.iter().any(|m| m.line == new_mail.line) {
+                continue;
+            }
+            let kind = match attempts.first() {
+                None => MailEventKind::New,
+                Some(previous) => {
+                    new_mail.subject = previous.subject.clone();
+                    MailEventKind::StatusUpdate
+                }
+            };
+            let envelope = envelopes.touch(&new_mail.id, new_mail.timestamp);
+            if envelope.suppressed {
+                continue;
+            }
+            if !envelope.recipients.contains(&new_mail.to) {
+                envelope.recipients.push(new_mail.to.clone());
+            }
+            new_mail.from = envelope.from.clone();
+            metrics::record_delivery(&new_mail);
+            send_event(kind, &new_mail);
+            lock.push(new_mail);
+            updates += 1;
         }
+        // Only after the mails, so the senders of the same log are still attached to them
+        envelopes.prune();
         updates
     }
+
+    /// Removes all mails to or from addresses matching the rule and returns how many were removed
+    pub fn purge(&self, rule: &Rule) -> usize {
+        let mut mails = self.mails.lock();
+        let mut envelopes = self.envelopes.lock();
+        let mut purged = 0;
+        for db_mails in mails.by_address.values_mut() {
+            let before = db_mails.len();
+            db_mails.retain(|m| {
+                !rule.matches(&m.to) && !m.from.as_deref().is_some_and(|f| rule.matches(f))
+            });
+            purged += before - db_mails.len();
+        }
+        mails.by_address.retain(|_, db_mails| !db_mails.is_empty());
+        mails.rebuild_index();
+        for envelope in envelopes.by_id.values_mut() {
+            envelope.recipients.retain(|r| !rule.matches(r));
+            // Keeps suppressing later attempts of the mail, without keeping the erased address
+            if envelope.from.as_deref().is_some_and(|f| rule.matches(f)) {
+                envelope.from = None;
+                envelope.suppressed = true;
+                envelope.recipients.clear();
+            }
+        }
+        purged
+    }
+
+    /// inserts the mails and senders of a parsed mail log into local MAIL_DB
+    pub fn insert_mail_log(&self, mail_log: MailLog) -> i32 {
+        self.insert_senders(mail_log.senders);
+        self.insert_mails(mail_log.mails)
+    }
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Mail {
-    id: String,
-    pub line: Option<String>,
+    /// Postfix queue ID
+    pub id: String,
+    pub timestamp: Option<DateTime<Utc>>,
+    /// Envelope sender, empty for bounces
+    pub from: Option<String>,
+    pub to: String,
+    /// e.g. sent, deferred or bounced
+    pub status: Option<String>,
+    pub relay: Option<String>,
+    pub dsn: Option<String>,
     pub subject: Option<String>,
+    /// The raw log line of the delivery attempt
+    pub line: Option<String>,
+    /// Order in which mails were inserted into the DB, unique within MAIL_DB
     #[serde(skip)]
-    to: String,
+    pub seq: u64,
+}
+
+#[cfg(test)]
+impl Mail {
+    /// A delivery attempt with only its ID and recipient, the other fields are set by struct update
+    pub fn for_test(id: &str, to: &str) -> Self {
+        Mail {
+            id: id.to_string(),
+            timestamp: None,
+            from: None,
+            to: to.to_string(),
+            status: None,
+            relay: None,
+            dsn: None,
+            subject: None,
+            line: None,
+            seq: 0,
+        }
+    }
+}
+
+impl Mail {
+    /// Key that mails are ordered by when queried: timestamp, then insertion order
+    pub fn sort_key(&self) -> (i64, u64) {
+        let timestamp = self.timestamp.map_or(i64::MIN, |t| t.timestamp());
+        (timestamp, self.seq)
+    }
+}
+
+/// Envelope sender of a mail, as logged by postfix/qmgr
+#[derive(Debug)]
+pub struct Sender {
+    id: String,
+    timestamp: Option<DateTime<Utc>>,
+    from: String,
+}
+
+/// Recipients and senders parsed from a mail log
+#[derive(Debug, Default)]
+pub struct MailLog {
+    pub mails: Vec<Mail>,
+    pub senders: Vec<Sender>,
 }
 
-type DynamicIterator = Box<dyn Iterator<Item=Result<Vec<u8>, std::io::Error>> + Send>;
+type DynamicIterator = Box<dyn Iterator<Item = Result<Vec<u8>, std::io::Error>> + Send>;
 
 pub struct FileLines(DynamicIterator);
 
 impl FileLines {
     /// Returns a line-based buffered iterator for given file,
     /// that either decompresses a .gz file or opens a regular file
-    fn new(file_name: &PathBuf) -> Result<Self> {
+    pub fn new(file_name: &PathBuf) -> Result<Self> {
         let f = File::open(file_name)
             .with_context(|| format!("trying to open {}", file_name.display()))?;
-        if let Some(extension) = file_name.extension() {
-            if extension == "gz" {
-                return Ok(FileLines(Box::new(BufReader::new(GzDecoder::new(f)).byte_lines().into_iter())));
-            } else if extension == "zst" || extension == "zstd" {
-                let decoder = zstd::Decoder::new(f)?;
+        let extension = file_name
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default();
+        FileLines::decompress(f, extension)
+    }
+
+    /// Returns a line-based buffered iterator for given reader,
+    /// that decompresses it if the extension is gz, zst or zstd
+    pub fn decompress(reader: impl Read + Send + 'static, extension: &str) -> Result<Self> {
+        match extension {
+            "gz" => Ok(FileLines(Box::new(
+                BufReader::new(GzDecoder::new(reader))
+                    .byte_lines()
+                    .into_iter(),
+            ))),
+            "zst" | "zstd" => {
+                let decoder = zstd::Decoder::new(reader)?;
                 let iter = BufReader::new(decoder).byte_lines().into_iter();
-                return Ok(FileLines(Box::new(iter)));
+                Ok(FileLines(Box::new(iter)))
             }
+            _ => Ok(FileLines(Box::new(
+                BufReader::new(reader).byte_lines().into_iter(),
+            ))),
         }
-        Ok(FileLines(Box::new(BufReader::new(f).byte_lines().into_iter())))
     }
 }
 
@@ -111,7 +461,7 @@ impl From<File> for FileLines {
 
 impl FileLines {
     pub fn into_iter(self) -> DynamicIterator {
-        self.0.into_iter()
+        self.0
     }
 }
 
@@ -131,6 +481,44 @@ fn id_from_log_line(line: &str) -> Option<&str> {
     Some(id)
 }
 
+/// Parses the timestamp of a syslog line, either RFC 3339 or the traditional
+/// "Jan  2 10:11:12" format. The latter has no year, so the current year is assumed,
+/// unless that would put the line in the future (i.e. the log is from last year).
+fn timestamp_from_log_line(line: &str) -> Option<DateTime<Utc>> {
+    let first_field = line.split_whitespace().next()?;
+    if let Ok(t) = DateTime::parse_from_rfc3339(first_field) {
+        return Some(t.with_timezone(&Utc));
+    }
+    let now = Local::now();
+    let syslog_timestamp = line.get(..15)?;
+    let parse = |year: i32| {
+        let naive = NaiveDateTime::parse_from_str(
+            &format!("{year} {syslog_timestamp}"),
+            "%Y %b %e %H:%M:%S",
+        )
+        .ok()?;
+        Local.from_local_datetime(&naive).earliest()
+    };
+    let mut timestamp = parse(now.year())?;
+    if timestamp > now + chrono::Duration::days(1) {
+        timestamp = parse(now.year() - 1)?;
+    }
+    Some(timestamp.with_timezone(&Utc))
+}
+
+/// Returns the envelope sender of a postfix/qmgr line, which is empty for bounces
+fn sender_from_log_line(line: &str) -> Option<&str> {
+    let (_, rest) = line.split_once("from=<")?;
+    let (from, _) = rest.split_once('>')?;
+    Some(from)
+}
+
+/// Returns the value of a `key=value` field of a postfix log line, e.g. "sent" for `status=sent (250 Ok)`
+fn field_from_log_line<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let (_, rest) = line.split_once(&format!(" {key}="))?;
+    rest.split([',', ' ']).next().filter(|v| !v.is_empty())
+}
+
 fn email_from_log_line(line: &str) -> Option<&str> {
     let split_1 = line.split('<').take(2).collect::<Vec<_>>();
     if split_1.len() != 2 {
@@ -144,76 +532,128 @@ fn email_from_log_line(line: &str) -> Option<&str> {
     }
 }
 
-async fn init_mail_log() -> Result<i32> {
-    let files = &Config::global().log.files;
-    let dir = &Config::global().log.dir;
+async fn init_mail_log(files: &[PathBuf]) -> Result<i32> {
     let mut inserts_total = 0;
-    for file in files {
+    for file_path in files {
         task::yield_now().await; // Yield to be able to cancel this task
-        let file_path: PathBuf = [dir, file].iter().collect();
-        let reader = match FileLines::new(&file_path).with_context(|| format!("getting reader for: {}", file_path.display())) {
+        let reader = match FileLines::new(file_path)
+            .with_context(|| format!("getting reader for: {}", file_path.display()))
+        {
             Ok(r) => r,
             Err(why) => {
                 error!("{}", why);
-                continue
+                HEALTH.set_file_state(file_path, FileState::Failed);
+                continue;
             }
         };
         info!("Loading mail logs from file: {}...", file_path.display());
-        let mails = parse_mails(reader)
-            .with_context(|| format!("parsing emails for: {}", file_path.display()))?;
-        inserts_total += MAIL_DB.insert_mails(mails);
+        // A corrupt file fails the same way on every attempt, so it doesn't fail the others
+        let mail_log = match parse_mails(reader, &file_path.display().to_string())
+            .with_context(|| format!("parsing emails for: {}", file_path.display()))
+        {
+            Ok(l) => l,
+            Err(why) => {
+                error!("{:#}", why);
+                HEALTH.set_file_state(file_path, FileState::Failed);
+                continue;
+            }
+        };
+        inserts_total += MAIL_DB.insert_mail_log(mail_log);
+        HEALTH.set_file_state(file_path, FileState::Loaded);
     }
     Ok(inserts_total)
 }
 
-/// Parse mails from given FileLines reader and return them
-pub fn parse_mails(reader: FileLines) -> Result<Vec<Mail>> {
-    let mut mails: Vec<Mail> = vec![];
+/// Parse mails (postfix/smtp) and their senders (postfix/qmgr) from given FileLines reader and return them.
+/// Lines are counted in the metrics of the given source.
+pub fn parse_mails(reader: FileLines, source: &str) -> Result<MailLog> {
+    let mut mail_log = MailLog::default();
+    let mut stats = metrics::ParseStats::new(source);
     let (mut email, mut id) = (String::new(), String::new());
     for line in reader.into_iter() {
-        let bytes: &[u8] = &line.with_context(|| "while reading line from FileLines")?;
+        let bytes: &[u8] = &stats
+            .read(line)
+            .with_context(|| "while reading line from FileLines")?;
         let line = String::from_utf8_lossy(bytes);
+        if line.contains("postfix/qmgr[") {
+            if let (Some(id), Some(from)) = (id_from_log_line(&line), sender_from_log_line(&line)) {
+                mail_log.senders.push(Sender {
+                    id: id.to_string(),
+                    timestamp: timestamp_from_log_line(&line),
+                    from: from.to_string(),
+                });
+                stats.parsed();
+            }
+            continue;
+        }
         if !line.contains("postfix/smtp[") {
             continue;
         }
         email.clear();
         id.clear();
         email = match email_from_log_line(&line) {
-            None => continue,
+            None => {
+                stats.failed();
+                continue;
+            }
             Some(v) => v.into(),
         };
         id = match id_from_log_line(&line) {
-            None => continue,
+            None => {
+                stats.failed();
+                continue;
+            }
             Some(v) => v.into(),
         };
         if !email.is_empty() && !id.is_empty() {
-            mails.push(Mail {
+            mail_log.mails.push(Mail {
                 to: email.clone(),
                 id: id.clone(),
+                timestamp: timestamp_from_log_line(&line),
                 subject: None,
+                from: None,
+                status: field_from_log_line(&line, "status").map(String::from),
+                relay: field_from_log_line(&line, "relay").map(String::from),
+                dsn: field_from_log_line(&line, "dsn").map(String::from),
                 line: Some(line.to_string()),
+                seq: 0,
             });
+            stats.parsed();
         }
     }
-    Ok(mails)
+    Ok(mail_log)
 }
 
-async fn init_mail_subjects() -> Result<i32> {
-    let files = &Config::global().mail.files;
-    let dir = &Config::global().mail.dir;
+async fn init_mail_subjects(files: &[PathBuf]) -> Result<i32> {
     let mut subjects_updated = 0;
-    for file in files {
+    for file_path in files {
         task::yield_now().await; // Yield to be able to cancel this task
-        let file_path: PathBuf = [dir, file].iter().collect();
-        let reader = FileLines::new(&file_path)
-            .with_context(|| format!("getting reader for {}", file_path.display()))?;
+        let reader = match FileLines::new(file_path)
+            .with_context(|| format!("getting reader for {}", file_path.display()))
+        {
+            Ok(r) => r,
+            Err(why) => {
+                error!("{}", why);
+                HEALTH.set_file_state(file_path, FileState::Failed);
+                continue;
+            }
+        };
         info!(
             "Loading mail subjects from file: {}...",
             file_path.display()
         );
-        let mails_with_subject = parse_mail_subjects(reader)
-            .with_context(|| format!("parsing mail subjects for {}", file_path.display()))?;
+        let mails_with_subject = match parse_mail_subjects(reader, &file_path.display().to_string())
+            .with_context(|| format!("parsing mail subjects for {}", file_path.display()))
+        {
+            Ok(m) => m,
+            Err(why) => {
+                error!("{:#}", why);
+                HEALTH.set_file_state(file_path, FileState::Failed);
+                continue;
+            }
+        };
         subjects_updated += MAIL_DB.update_mail_subjects(mails_with_subject);
+        HEALTH.set_file_state(file_path, FileState::Loaded);
     }
     Ok(subjects_updated)
 }
@@ -221,12 +661,15 @@ async fn init_mail_subjects() -> Result<i32> {
 /// parses FileLines (dynamic, line-based and buffered file reader)
 /// to find an email ID, an email address and a subject.
 /// Update the MAIL_DB if a matching email address and ID are found
-pub fn parse_mail_subjects(reader: FileLines) -> Result<Vec<Mail>> {
+pub fn parse_mail_subjects(reader: FileLines, source: &str) -> Result<Vec<Mail>> {
     let (mut id, mut subject, mut to) = (String::new(), String::new(), String::new());
     let mut mails_with_subjects: Vec<Mail> = vec![];
+    let mut stats = metrics::ParseStats::new(source);
     let mut parse_mail = false;
     for line in reader.0 {
-        let bytes: &[u8] = &line.with_context(|| "while reading line from FileLines")?;
+        let bytes: &[u8] = &stats
+            .read(line)
+            .with_context(|| "while reading line from FileLines")?;
         let line = String::from_utf8_lossy(bytes);
         // "ESMTPS id" should indicate the start of an email, so start parsing the mail
         if !parse_mail && line.contains("ESMTPS id") {
@@ -253,36 +696,74 @@ pub fn parse_mail_subjects(reader: FileLines) -> Result<Vec<Mail>> {
             parse_mail = false;
             mails_with_subjects.push(Mail {
                 id: id.clone(),
+                timestamp: None,
                 line: None,
                 subject: Some(subject.clone()),
+                from: None,
                 to: to.clone(),
+                status: None,
+                relay: None,
+                dsn: None,
+                seq: 0,
             });
+            stats.parsed();
         }
     }
     Ok(mails_with_subjects)
 }
 
+/// Paths of the configured log files and mail files
+pub fn configured_files(config: &Config) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    let paths = |dir: &String, files: &[String]| {
+        files
+            .iter()
+            .map(|file| [dir, file].iter().collect())
+            .collect::<Vec<PathBuf>>()
+    };
+    (
+        paths(&config.log.dir, &config.log.files),
+        paths(&config.mail.dir, &config.mail.files),
+    )
+}
+
+/// Loads log files and then the subjects of mail files into the DB
+pub async fn load_files(log_files: &[PathBuf], mail_files: &[PathBuf]) -> Result<()> {
+    let files = [log_files, mail_files].concat();
+    HEALTH.start_loading(&files);
+    let res = async {
+        info!(
+            "inserted {} emails into mail DB",
+            init_mail_log(log_files).await?
+        );
+        info!(
+            "inserted {} subjects into mail DB",
+            init_mail_subjects(mail_files).await?
+        );
+        Ok(())
+    }
+    .await;
+    HEALTH.finish_loading(&files);
+    res
+}
+
 /// initialize in-memory mail database from configured mail paths
 pub async fn init_mail() -> Result<String> {
     task::yield_now().await;
     // Yield to be able to cancel this task
     info!("Loading configured email into DB...");
-    info!("inserted {} emails into mail DB", init_mail_log().await?);
-    info!(
-        "inserted {} subjects into mail DB",
-        init_mail_subjects().await?
-    );
-    Ok(String::from("Loading emails done."))
+    let (log_files, mail_files) = configured_files(&Config::global());
+    HEALTH.set_warming_up(true);
+    let res = load_files(&log_files, &mail_files).await;
+    HEALTH.set_warming_up(false);
+    res.map(|()| String::from("Loading emails done."))
 }
 
-/// tail the configured mail tail file (usually /var/mail/root) and update the
+/// tail the given mail file (usually /var/mail/root) and update the
 /// in memory mail database with the subjects found.
-/// Function needs a delay because the mail contents should be parsed some time after
-/// mails have been received to line them up to logfiles.
-pub async fn tail_mail(delay: Duration) -> Result<String> {
-    let file_path: PathBuf = [&Config::global().mail.dir, &Config::global().mail.tail]
-        .iter()
-        .collect();
+/// Subjects are applied after the configured parsing delay, because the mail contents should be
+/// parsed some time after mails have been received to line them up to logfiles.
+pub async fn tail_mail(file_path: PathBuf) -> Result<String> {
+    let _alive = HEALTH.tailer_alive(&file_path);
     let (mut file_tail, mut rx_lines) = FileTail::new(&file_path)
         .with_context(|| format!("when tailing mail log file: {}", file_path.display()))?;
     {
@@ -290,13 +771,14 @@ pub async fn tail_mail(delay: Duration) -> Result<String> {
         tokio::spawn(async move {
             info!("Tailing mail file: {}...", file_path.display());
             while let Some(reader) = rx_lines.recv().await {
-                let parse_res = parse_mail_subjects(reader)
+                let parse_res = parse_mail_subjects(reader, &file_path.display().to_string())
                     .with_context(|| format!("parsing mail subjects for {}", file_path.display()));
                 match parse_res {
                     Ok(mails_with_subjects) => {
                         // Seeing as two files are being tailed simultaneously, there is a large chance that the DB isn't updated yet
                         // for the new mail. Sleep for supplied duration
                         let file_path = file_path.clone();
+                        let delay = Duration::from_secs(Config::global().mail_parsing_delay);
                         task::spawn(async move {
                             time::sleep(delay).await;
                             let updates = MAIL_DB.update_mail_subjects(mails_with_subjects);
@@ -321,12 +803,10 @@ pub async fn tail_mail(delay: Duration) -> Result<String> {
     )
 }
 
-/// tail the configured mail log file (usually /var/log/mail.info) and update the
+/// tail the given mail log file (usually /var/log/mail.info) and update the
 /// in memory mail database accordingly.
-pub async fn tail_mail_log() -> Result<String> {
-    let file_path: PathBuf = [&Config::global().log.dir, &Config::global().log.tail]
-        .iter()
-        .collect();
+pub async fn tail_mail_log(file_path: PathBuf) -> Result<String> {
+    let _alive = HEALTH.tailer_alive(&file_path);
     let (mut file_tail, mut rx_lines) = FileTail::new(&file_path)
         .with_context(|| format!("when tailing mail log file: {}", file_path.display()))?;
     {
@@ -334,11 +814,11 @@ pub async fn tail_mail_log() -> Result<String> {
         tokio::spawn(async move {
             info!("Tailing mail logfile: {}...", file_path.display());
             while let Some(reader) = rx_lines.recv().await {
-                let parse_res = parse_mails(reader)
+                let parse_res = parse_mails(reader, &file_path.display().to_string())
                     .with_context(|| format!("parsing emails for: {}", file_path.display()));
                 match parse_res {
-                    Ok(mails) => {
-                        let inserts = MAIL_DB.insert_mails(mails);
+                    Ok(mail_log) => {
+                        let inserts = MAIL_DB.insert_mail_log(mail_log);
                         if inserts > 0 {
                             debug!("Inserted {inserts} mails from {}", file_path.display())
                         };
@@ -358,3 +838,76 @@ pub async fn tail_mail_log() -> Result<String> {
         res
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn envelopes_age_out_by_log_time() {
+        let at = |timestamp| Utc.timestamp_opt(timestamp, 0).single();
+        let mut envelopes = Envelopes::default();
+        envelopes.touch("OLD", at(999_999));
+        envelopes
+            .touch("KEPT", at(1_000_000))
+            .recipients
+            .push("bob@example.com".into());
+        envelopes.touch("KEPT", at(1_000_000 + ENVELOPE_RETENTION));
+        envelopes.touch("UNDATED", None);
+        envelopes.prune();
+        let mut ids: Vec<_> = envelopes.by_id.keys().map(String::as_str).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, ["KEPT", "UNDATED"]);
+        assert_eq!(envelopes.by_id["KEPT"].recipients, ["bob@example.com"]);
+    }
+
+    fn sender(id: &str, from: &str) -> Sender {
+        Sender {
+            id: id.into(),
+            timestamp: None,
+            from: from.into(),
+        }
+    }
+
+    fn attempt(id: &str, to: &str, line: &str) -> Mail {
+        Mail {
+            line: Some(line.into()),
+            ..Mail::for_test(id, to)
+        }
+    }
+
+    #[test]
+    fn purge_suppresses_later_attempts_without_keeping_the_sender() {
+        let db = MailDB::new();
+        db.insert_senders(vec![sender("A", "alice@sender.org")]);
+        assert_eq!(db.insert_mails(vec![attempt("A", "bob@x.com", "1")]), 1);
+        let rule: Rule = "alice@sender.org".parse().unwrap();
+        assert_eq!(db.purge(&rule), 1);
+        assert!(db.lock().by_address().is_empty());
+        let envelopes = db.envelopes.lock();
+        assert!(envelopes.by_id["A"].suppressed);
+        assert_eq!(envelopes.by_id["A"].from, None);
+        drop(envelopes);
+        assert_eq!(db.insert_mails(vec![attempt("A", "bob@x.com", "2")]), 0);
+        // A new sender means postfix reused the ID for another mail
+        db.insert_senders(vec![sender("A", "carol@sender.org")]);
+        assert_eq!(db.insert_mails(vec![attempt("A", "bob@x.com", "3")]), 1);
+        let mails = db.lock();
+        let from = mails.by_address()["bob@x.com"][0].from.as_deref();
+        assert_eq!(from, Some("carol@sender.org"));
+    }
+
+    #[test]
+    fn reused_ids_start_a_new_envelope() {
+        let db = MailDB::new();
+        db.insert_senders(vec![sender("A", "alice@sender.org")]);
+        db.insert_mails(vec![attempt("A", "bob@x.com", "1")]);
+        db.insert_senders(vec![sender("A", "alice@sender.org")]);
+        assert_eq!(db.envelopes.lock().by_id["A"].recipients, ["bob@x.com"]);
+        db.insert_senders(vec![sender("A", "carol@sender.org")]);
+        assert!(db.envelopes.lock().by_id["A"].recipients.is_empty());
+        let mails = db.lock();
+        let from = mails.by_address()["bob@x.com"][0].from.as_deref();
+        assert_eq!(from, Some("alice@sender.org"));
+    }
+}
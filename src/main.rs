@@ -1,73 +1,162 @@
 extern crate core;
 
 use std::net::SocketAddr;
-use std::path::PathBuf;
-use std::time::Duration;
 
+use crate::cli::{Cli, Command};
 use crate::config::{read_config, Config};
-use crate::endpoints::find_mail;
-use crate::mail::{init_mail, tail_mail, tail_mail_log};
+use crate::endpoints::{
+    erase, find_mail, find_sent_mail, healthz, import, import_progress, imports, not_found,
+    prometheus_metrics, readyz, stats, stream_mail,
+};
+use crate::mail::init_mail;
+use crate::supervisor::supervise;
 use crate::tail::FileTail;
 use anyhow::{bail, Result};
-use axum::routing::get;
+use axum::http::header::AUTHORIZATION;
+use axum::middleware;
+use axum::routing::{get, post};
 use axum::Router;
-use axum_server::tls_rustls::RustlsConfig;
+use clap::Parser;
 use env_logger::Env;
 use log::info;
 use once_cell::sync::OnceCell;
+use parking_lot::RwLock;
+use std::sync::Arc;
 use tokio::select;
 use tokio::task::JoinSet;
 use tower_http::cors;
 use tower_http::cors::CorsLayer;
 
+mod audit;
+mod auth;
+mod check;
+mod cli;
+mod client;
 mod config;
 mod endpoints;
+mod error;
+mod export;
+mod health;
+mod import;
+mod index;
+mod limit;
 mod mail;
+mod metrics;
+mod offline;
+mod openapi;
+mod query;
+mod redact;
+mod reload;
+mod stats;
+mod supervisor;
+mod suppress;
 mod tail;
+mod tls;
+mod ui;
 
-pub(crate) static CONFIG: OnceCell<Config> = OnceCell::new();
+/// Set once at startup, replaced whenever the config is reloaded
+pub(crate) static CONFIG: OnceCell<RwLock<Arc<Config>>> = OnceCell::new();
 
 /// Start the API to query for mails and subjects
 async fn start_http() -> Result<String> {
-    let socket_addr: SocketAddr = format!(
-        "{}:{}",
-        Config::global().listen.ip,
-        Config::global().listen.port,
-    )
-    .parse()?;
-    let cors = CorsLayer::new().allow_origin(cors::AllowOrigin::any());
-    let app = Router::new()
+    // Changes to the listen address, TLS and CORS take effect after a restart
+    let config = Config::global();
+    let socket_addr: SocketAddr = format!("{}:{}", config.listen.ip, config.listen.port).parse()?;
+    let allow_origin = match &config.cors_origins {
+        None => cors::AllowOrigin::any(),
+        Some(origins) => cors::AllowOrigin::list(
+            origins
+                .iter()
+                .map(|o| o.parse())
+                .collect::<Result<Vec<_>, _>>()?,
+        ),
+    };
+    let cors = CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_headers([AUTHORIZATION])
+        .expose_headers([export::TOTAL_COUNT.clone(), export::NEXT_CURSOR.clone()]);
+    let authenticated = Router::new()
         .route("/find_mail", get(find_mail))
+        .route("/find_sent_mail", get(find_sent_mail))
+        .route("/stream_mail", get(stream_mail))
+        .route("/stats", get(stats))
+        .route("/admin/erase", post(erase))
+        .route("/admin/import", post(import))
+        .route("/admin/imports", get(imports))
+        .route("/admin/imports/:id", get(import_progress))
+        .route_layer(middleware::from_fn(limit::rate_limit))
+        .route_layer(middleware::from_fn(audit::audit))
+        .route("/metrics", get(prometheus_metrics))
+        .route_layer(middleware::from_fn(auth::authenticate));
+    let app = Router::new()
+        .route("/", get(ui::index))
+        .route("/openapi.json", get(openapi::openapi))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .merge(authenticated)
+        .fallback(not_found)
+        .route_layer(middleware::from_fn(metrics::track_query_duration))
         .layer(cors);
     info!("Server listening on {}", socket_addr);
-    match &Config::global().tls {
+    match &config.tls {
         Some(c) => {
-            let rustls_config =
-                RustlsConfig::from_pem_file(PathBuf::from(&c.cert), PathBuf::from(&c.key)).await?;
-            axum_server::bind_rustls(socket_addr, rustls_config)
-                .serve(app.into_make_service())
-                .await?
+            let rustls_config = tls::rustls_config(c)?;
+            let server = axum_server::bind(socket_addr)
+                .acceptor(tls::ClientCertAcceptor::new(rustls_config.clone()))
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>());
+            select! {
+                res = server => res?,
+                res = tls::reload_certificates(c.clone(), rustls_config) => return res,
+            }
         }
         None => {
             axum::Server::bind(&socket_addr)
-                .serve(app.into_make_service())
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
                 .await?;
         }
     }
     Ok(String::from("HTTP server stopped"))
 }
 
+/// Prints a report of the config and fails if it has errors
+fn check_config(cli: &Cli) -> Result<()> {
+    let config = read_config(&cli.config)?;
+    let report = check::check(&config);
+    println!("{report}");
+    if report.has_errors() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    env_logger::init_from_env(Env::default().filter_or("RUST_LOG", "info"));
-    CONFIG.set(read_config()?).unwrap();
+    let cli = Cli::parse();
+    // Only problems matter to one-shot commands
+    let level = match cli.command {
+        Some(Command::Query(_) | Command::Client(_)) => "warn",
+        _ => "info",
+    };
+    env_logger::init_from_env(Env::default().filter_or("RUST_LOG", level));
+    match &cli.command {
+        Some(Command::CheckConfig) => return check_config(&cli),
+        Some(Command::Query(args)) => return offline::query(args),
+        Some(Command::Client(args)) => return client::run(args).await,
+        None => {}
+    }
+    CONFIG
+        .set(RwLock::new(Arc::new(read_config(&cli.config)?)))
+        .unwrap();
+    let report = check::check(&Config::global());
+    report.log();
+    if report.has_errors() {
+        bail!("Invalid config, run check-config for a full report");
+    }
+    suppress::load()?;
     let mut tasks = JoinSet::new();
     tasks.spawn(start_http());
-    tasks.spawn(init_mail());
-    tasks.spawn(tail_mail_log());
-    tasks.spawn(tail_mail(Duration::from_secs(
-        Config::global().mail_parsing_delay,
-    )));
+    tasks.spawn(supervise("init_mail", init_mail));
+    tasks.spawn(reload::watch_config(cli.config));
     loop {
         select! {
             _ = tokio::signal::ctrl_c() => {
@@ -0,0 +1,199 @@
+use crate::mail::{Mail, MAIL_DB};
+use axum::extract::MatchedPath;
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
+    Encoder, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+use rustc_hash::FxHashMap;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Counts lines while a file is parsed and adds them to the metrics when dropped
+pub struct ParseStats<'a> {
+    source: &'a str,
+    read: u64,
+    parsed: u64,
+    failures: u64,
+}
+
+impl<'a> ParseStats<'a> {
+    pub fn new(source: &'a str) -> Self {
+        ParseStats {
+            source,
+            read: 0,
+            parsed: 0,
+            failures: 0,
+        }
+    }
+
+    /// Counts a line that was read, or a failure if it couldn't be read
+    pub fn read<T>(&mut self, line: std::io::Result<T>) -> std::io::Result<T> {
+        match line {
+            Ok(l) => {
+                self.read += 1;
+                Ok(l)
+            }
+            Err(why) => {
+                self.failures += 1;
+                Err(why)
+            }
+        }
+    }
+
+    pub fn parsed(&mut self) {
+        self.parsed += 1;
+    }
+
+    pub fn failed(&mut self) {
+        self.failures += 1;
+    }
+}
+
+impl Drop for ParseStats<'_> {
+    fn drop(&mut self) {
+        LINES_READ
+            .with_label_values(&[self.source])
+            .inc_by(self.read);
+        LINES_PARSED
+            .with_label_values(&[self.source])
+            .inc_by(self.parsed);
+        PARSE_FAILURES
+            .with_label_values(&[self.source])
+            .inc_by(self.failures);
+    }
+}
+
+static LINES_READ: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "lmdb_lines_read_total",
+        "Lines read from mail logs and mail files",
+        &["source"]
+    )
+    .unwrap()
+});
+
+static LINES_PARSED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "lmdb_lines_parsed_total",
+        "Lines that yielded a mail, sender or subject",
+        &["source"]
+    )
+    .unwrap()
+});
+
+static PARSE_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "lmdb_parse_failures_total",
+        "Lines that could not be read, or relevant lines without an address or ID",
+        &["source"]
+    )
+    .unwrap()
+});
+
+pub static SUBJECTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "lmdb_subjects_total",
+        "Subjects that were matched to a mail, or orphaned because no mail was found",
+        &["result"]
+    )
+    .unwrap()
+});
+
+static DELIVERIES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "lmdb_deliveries_total",
+        "Mails inserted into the DB by delivery status and recipient domain",
+        &["status", "domain"]
+    )
+    .unwrap()
+});
+
+pub static TASK_RESTARTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "lmdb_task_restarts_total",
+        "Restarts of ingestion tasks after they failed",
+        &["task"]
+    )
+    .unwrap()
+});
+
+static QUERY_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "lmdb_query_duration_seconds",
+        "Duration of API requests",
+        &["endpoint"]
+    )
+    .unwrap()
+});
+
+static RECORDS: Lazy<IntGauge> =
+    Lazy::new(|| register_int_gauge!("lmdb_records", "Mails in the DB").unwrap());
+
+static ADDRESSES: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!("lmdb_addresses", "Distinct recipient addresses in the DB").unwrap()
+});
+
+static TAIL_LAG: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "lmdb_tail_lag_bytes",
+        "Bytes of a tailed file that have not been read yet",
+        &["source"]
+    )
+    .unwrap()
+});
+
+/// Read positions of tailed files, to calculate the tail lag when scraped
+static TAIL_POSITIONS: Lazy<Mutex<FxHashMap<PathBuf, u64>>> =
+    Lazy::new(|| Mutex::new(FxHashMap::default()));
+
+pub fn record_delivery(mail: &Mail) {
+    let domain = mail
+        .to
+        .rsplit_once('@')
+        .map_or(String::new(), |(_, domain)| domain.to_lowercase());
+    DELIVERIES
+        .with_label_values(&[mail.status.as_deref().unwrap_or("unknown"), &domain])
+        .inc();
+}
+
+pub fn set_tail_position(file_path: &Path, pos: u64) {
+    TAIL_POSITIONS.lock().insert(file_path.to_path_buf(), pos);
+}
+
+/// Records the duration of every request by its route
+pub async fn track_query_duration<B>(req: Request<B>, next: Next<B>) -> Response {
+    let endpoint = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or_else(|| req.uri().path().to_string(), |p| p.as_str().to_string());
+    let start = Instant::now();
+    let response = next.run(req).await;
+    QUERY_DURATION
+        .with_label_values(&[&endpoint])
+        .observe(start.elapsed().as_secs_f64());
+    response
+}
+
+/// Updates the gauges that are derived from current state and encodes all metrics
+pub fn gather() -> anyhow::Result<String> {
+    {
+        let mdb = MAIL_DB.lock();
+        let by_address = mdb.by_address();
+        ADDRESSES.set(by_address.len() as i64);
+        RECORDS.set(by_address.values().map(|v| v.len() as i64).sum());
+    }
+    for (file_path, pos) in TAIL_POSITIONS.lock().iter() {
+        let size = std::fs::metadata(file_path).map_or(0, |m| m.len());
+        TAIL_LAG
+            .with_label_values(&[&file_path.display().to_string()])
+            .set(size.saturating_sub(*pos) as i64);
+    }
+    let mut buffer = vec![];
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
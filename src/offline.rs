@@ -0,0 +1,126 @@
+use crate::cli::{FilterArgs, OutputFormat, QueryArgs};
+use crate::index::tokenize;
+use crate::mail::{parse_mail_subjects, parse_mails, FileLines, Mail, MailDB};
+use crate::query::{paginate, Budget, MailFilter, MatchMode, Matcher};
+use anyhow::{bail, Context, Result};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+const TABLE_HEADER: [&str; 6] = ["TIMESTAMP", "FROM", "TO", "STATUS", "DSN", "SUBJECT"];
+
+/// Compiles the filters like find_mail does, without the limits of the config
+fn filter(args: &FilterArgs) -> Result<MailFilter> {
+    if args.subject_match == MatchMode::Domain {
+        bail!("--subject-match does not support domain");
+    }
+    let compile = |filter: &Option<String>, mode: MatchMode, name: &str| {
+        filter
+            .as_deref()
+            .map(|f| Matcher::new(mode, f))
+            .transpose()
+            .with_context(|| name.to_string())
+    };
+    Ok(MailFilter {
+        address: compile(
+            &args.email_address_filter,
+            args.email_address_match,
+            "--email-address-filter",
+        )?,
+        subject: compile(&args.subject_filter, args.subject_match, "--subject-filter")?,
+        from: compile(&args.from_filter, args.from_match, "--from-filter")?,
+        text: args
+            .text_filter
+            .as_deref()
+            .map(|t| tokenize(t).collect())
+            .unwrap_or_default(),
+        domains: None,
+        since: args.since,
+        until: args.until,
+    })
+}
+
+/// Loads the log files and then the subjects of the mail files into a DB of its own
+fn load(args: &QueryArgs) -> Result<MailDB> {
+    let db = MailDB::new();
+    for file_path in &args.files {
+        let source = file_path.display().to_string();
+        let mail_log = parse_mails(FileLines::new(file_path)?, &source)
+            .with_context(|| format!("parsing emails for: {source}"))?;
+        db.insert_mail_log(mail_log);
+    }
+    for file_path in &args.subject_files {
+        let source = file_path.display().to_string();
+        let mails_with_subjects = parse_mail_subjects(FileLines::new(file_path)?, &source)
+            .with_context(|| format!("parsing mail subjects for {source}"))?;
+        db.update_mail_subjects(mails_with_subjects);
+    }
+    Ok(db)
+}
+
+/// Prints aligned columns, the subject last as it varies the most in length
+fn print_table(mails: &[&Mail], out: &mut impl Write) -> io::Result<()> {
+    let rows: Vec<[String; 6]> = mails
+        .iter()
+        .map(|m| {
+            [
+                m.timestamp
+                    .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_default(),
+                m.from.clone().unwrap_or_default(),
+                m.to.clone(),
+                m.status.clone().unwrap_or_default(),
+                m.dsn.clone().unwrap_or_default(),
+                m.subject.clone().unwrap_or_default(),
+            ]
+        })
+        .collect();
+    let header = TABLE_HEADER.map(String::from);
+    let mut widths = [0; 6];
+    for row in std::iter::once(&header).chain(&rows) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in std::iter::once(&header).chain(&rows) {
+        let (last, cells) = row.split_last().unwrap();
+        for (cell, width) in cells.iter().zip(widths) {
+            write!(out, "{cell:<width$}  ")?;
+        }
+        writeln!(out, "{last}")?;
+    }
+    Ok(())
+}
+
+/// Answers a single query from files and prints the results, for archives that aren't served
+pub fn query(args: &QueryArgs) -> Result<()> {
+    let filter = filter(&args.filters)?;
+    let db = load(args)?;
+    let mails = db.lock();
+    let matches = filter.find(&mails, &Budget::unlimited())?;
+    let page = paginate(
+        matches,
+        args.filters.order,
+        None,
+        args.limit.unwrap_or(usize::MAX),
+    );
+    let mut out = io::stdout().lock();
+    match args.output {
+        OutputFormat::Table => print_table(&page.mails, &mut out)?,
+        OutputFormat::Json => {
+            let mut results: BTreeMap<&str, Vec<&Mail>> = BTreeMap::new();
+            for mail in &page.mails {
+                results.entry(&mail.to).or_default().push(mail);
+            }
+            serde_json::to_writer_pretty(
+                &mut out,
+                &json!({ "results": results, "total": page.total }),
+            )?;
+            writeln!(out)?;
+        }
+    }
+    if page.mails.len() < page.total {
+        eprintln!("Printed {} of {} mails", page.mails.len(), page.total);
+    }
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use crate::endpoints;
+use axum::Json;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+/// OpenAPI document of the API, generated from the endpoints and their types
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Linux MailDB"),
+    paths(
+        endpoints::find_mail,
+        endpoints::find_sent_mail,
+        endpoints::stream_mail,
+        endpoints::stats,
+        endpoints::prometheus_metrics,
+        endpoints::erase,
+        endpoints::import,
+        endpoints::imports,
+        endpoints::import_progress,
+        endpoints::healthz,
+        endpoints::readyz,
+    ),
+    components(schemas(
+        crate::error::ApiError,
+        crate::error::ErrorCode,
+        crate::error::ErrorResponse,
+        crate::mail::Mail,
+        crate::query::MatchMode,
+        crate::query::SortOrder,
+        crate::export::Format,
+        crate::stats::StatsGroupBy,
+        crate::stats::StatsGroup,
+        crate::stats::Bucket,
+        crate::import::ImportKind,
+        crate::import::ImportState,
+        crate::import::ImportReport,
+        crate::health::Readiness,
+        crate::health::TaskHealth,
+        endpoints::FindMailResponse,
+        endpoints::StatsResponse,
+        endpoints::EraseResponse,
+    )),
+    modifiers(&BearerAuth),
+    security(("bearer" = []))
+)]
+pub struct ApiDoc;
+
+/// Tokens are sent as bearer tokens, client certificates are negotiated by TLS and can't be described
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+/// Serves the OpenAPI document, it is public like the web UI
+pub async fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
@@ -0,0 +1,430 @@
+use crate::auth::in_domains;
+use crate::index::tokenize;
+use crate::mail::{Mail, Mails};
+use anyhow::{bail, Context};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+/// Amount of mails returned per page when no limit is given
+pub const DEFAULT_LIMIT: usize = 1000;
+/// Upper bound for the limit a client may request
+pub const MAX_LIMIT: usize = 10_000;
+
+/// Longest regex pattern a client may submit
+const MAX_REGEX_LEN: usize = 256;
+/// Upper bound for the compiled size of a regex, to prevent patterns that explode in memory
+const MAX_REGEX_SIZE: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ValueEnum, ToSchema)]
+#[serde(rename_all = "lowercase")]
+#[value(rename_all = "lower")]
+pub enum MatchMode {
+    /// Case-sensitive substring
+    Contains,
+    /// Case-insensitive substring
+    IContains,
+    /// Case-sensitive equality
+    Exact,
+    /// Case-insensitive equality
+    IExact,
+    /// Case-insensitive equality of the part after the '@' of an email address
+    Domain,
+    /// Regular expression, use `(?i)` to make it case-insensitive
+    Regex,
+}
+
+/// A filter compiled from a match mode and pattern
+#[derive(Debug)]
+pub enum Matcher {
+    Contains(String),
+    IContains(String),
+    Exact(String),
+    IExact(String),
+    Domain(String),
+    Regex(Regex),
+}
+
+impl Matcher {
+    pub fn new(mode: MatchMode, pattern: &str) -> anyhow::Result<Self> {
+        Ok(match mode {
+            MatchMode::Contains => Matcher::Contains(pattern.to_string()),
+            MatchMode::IContains => Matcher::IContains(pattern.to_lowercase()),
+            MatchMode::Exact => Matcher::Exact(pattern.to_string()),
+            MatchMode::IExact => Matcher::IExact(pattern.to_lowercase()),
+            MatchMode::Domain => Matcher::Domain(pattern.trim_start_matches('@').to_lowercase()),
+            MatchMode::Regex => {
+                if pattern.chars().count() > MAX_REGEX_LEN {
+                    bail!("regex pattern is longer than {MAX_REGEX_LEN} characters");
+                }
+                let regex = RegexBuilder::new(pattern)
+                    .size_limit(MAX_REGEX_SIZE)
+                    .dfa_size_limit(MAX_REGEX_SIZE)
+                    .build()
+                    .with_context(|| format!("invalid regex pattern '{pattern}'"))?;
+                Matcher::Regex(regex)
+            }
+        })
+    }
+
+    pub fn is_match(&self, haystack: &str) -> bool {
+        match self {
+            Matcher::Contains(p) => haystack.contains(p.as_str()),
+            Matcher::IContains(p) => haystack.to_lowercase().contains(p.as_str()),
+            Matcher::Exact(p) => haystack == p,
+            Matcher::IExact(p) => haystack.to_lowercase() == *p,
+            Matcher::Domain(p) => haystack
+                .rsplit_once('@')
+                .is_some_and(|(_, domain)| domain.eq_ignore_ascii_case(p)),
+            Matcher::Regex(r) => r.is_match(haystack),
+        }
+    }
+}
+
+/// Compiled filters of a mail query, mails have to match all filters that are set
+#[derive(Debug, Default)]
+pub struct MailFilter {
+    pub address: Option<Matcher>,
+    pub subject: Option<Matcher>,
+    pub from: Option<Matcher>,
+    /// Tokens that all have to occur in the log line of a mail
+    pub text: Vec<String>,
+    /// Domains that either the recipient or the sender has to be in, set by the identity querying
+    pub domains: Option<Vec<String>>,
+    /// Earliest delivery attempt, mails without a timestamp don't match if a time is set
+    pub since: Option<DateTime<Utc>>,
+    /// Delivery attempts before this time
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl MailFilter {
+    pub fn matches_address(&self, address: &str) -> bool {
+        self.address.as_ref().is_none_or(|m| m.is_match(address))
+    }
+
+    pub fn matches(&self, mail: &Mail) -> bool {
+        let matches_optional = |matcher: &Option<Matcher>, value: &Option<String>| match matcher {
+            None => true,
+            Some(matcher) => value.as_deref().is_some_and(|v| matcher.is_match(v)),
+        };
+        self.matches_address(&mail.to)
+            && matches_optional(&self.subject, &mail.subject)
+            && matches_optional(&self.from, &mail.from)
+            && self.matches_text(mail)
+            && self.matches_domains(mail)
+            && self.matches_time(mail)
+    }
+
+    fn matches_time(&self, mail: &Mail) -> bool {
+        if self.since.is_none() && self.until.is_none() {
+            return true;
+        }
+        mail.timestamp.is_some_and(|t| {
+            self.since.is_none_or(|since| t >= since) && self.until.is_none_or(|until| t < until)
+        })
+    }
+
+    fn matches_domains(&self, mail: &Mail) -> bool {
+        let Some(domains) = &self.domains else {
+            return true;
+        };
+        in_domains(&mail.to, domains)
+            || mail.from.as_deref().is_some_and(|f| in_domains(f, domains))
+    }
+
+    fn matches_text(&self, mail: &Mail) -> bool {
+        if self.text.is_empty() {
+            return true;
+        }
+        let Some(line) = &mail.line else {
+            return false;
+        };
+        let tokens = tokenize(line).collect::<Vec<_>>();
+        self.text.iter().all(|t| tokens.contains(t))
+    }
+
+    /// Returns all mails in the given DB that match this filter.
+    /// Uses the full-text index to narrow down the addresses if there is a text filter.
+    /// Finds the matching mails, failing once the query exceeds its budget
+    pub fn find<'a>(&self, mails: &'a Mails, budget: &Budget) -> Result<Vec<&'a Mail>, TooBroad> {
+        let by_address = mails.by_address();
+        let candidates: Box<dyn Iterator<Item = &Vec<Mail>>> = if self.text.is_empty() {
+            Box::new(
+                by_address
+                    .iter()
+                    .filter(|(address, _)| self.matches_address(address))
+                    .map(|(_, v)| v),
+            )
+        } else {
+            Box::new(
+                mails
+                    .index()
+                    .addresses(&self.text)
+                    .into_iter()
+                    .filter(|address| self.matches_address(address))
+                    .filter_map(|address| by_address.get(address)),
+            )
+        };
+        let mut found = vec![];
+        for (inspected, mail) in candidates.flatten().enumerate() {
+            budget.check_deadline(inspected + 1)?;
+            if self.matches(mail) {
+                found.push(mail);
+                budget.check_matched(found.len())?;
+            }
+        }
+        Ok(found)
+    }
+}
+
+/// A query matched too many mails, or held the DB too long
+#[derive(Error, Debug)]
+pub enum TooBroad {
+    #[error("query matches more than {0} mails, narrow down the filters")]
+    Matched(usize),
+    #[error("query took longer than {0}s, narrow down the filters")]
+    Timeout(u64),
+}
+
+/// Bounds on the work of a single query, so a broad filter can't hold the DB for everyone
+#[derive(Debug, Clone)]
+pub struct Budget {
+    max_matched: usize,
+    timeout: Duration,
+    deadline: Option<Instant>,
+}
+
+impl Budget {
+    /// The deadline starts now. Only matches count against `max_matched`, because they are what
+    /// a query keeps, while inspecting mails that don't match is bounded by the deadline.
+    pub fn new(max_matched: usize, timeout: Duration) -> Self {
+        Budget {
+            max_matched,
+            timeout,
+            deadline: Some(Instant::now() + timeout),
+        }
+    }
+
+    /// No bounds, for offline queries that hold up no one else
+    pub fn unlimited() -> Self {
+        Budget {
+            max_matched: usize::MAX,
+            timeout: Duration::ZERO,
+            deadline: None,
+        }
+    }
+
+    fn check_matched(&self, matched: usize) -> Result<(), TooBroad> {
+        if matched > self.max_matched {
+            return Err(TooBroad::Matched(self.max_matched));
+        }
+        Ok(())
+    }
+
+    /// Checks the clock only every 1024 mails, it is slow compared to matching a mail
+    fn check_deadline(&self, inspected: usize) -> Result<(), TooBroad> {
+        if inspected.is_multiple_of(1024) && self.deadline.is_some_and(|d| Instant::now() > d) {
+            return Err(TooBroad::Timeout(self.timeout.as_secs()));
+        }
+        Ok(())
+    }
+}
+
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ValueEnum, ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+#[value(rename_all = "lower")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Points at the last mail of a page, so the next page can continue after it.
+/// Encoded as `timestamp:sequence` for clients, who should treat it as opaque.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    timestamp: i64,
+    seq: u64,
+}
+
+impl Cursor {
+    fn key(&self) -> (i64, u64) {
+        (self.timestamp, self.seq)
+    }
+}
+
+impl From<&Mail> for Cursor {
+    fn from(mail: &Mail) -> Self {
+        let (timestamp, seq) = mail.sort_key();
+        Cursor { timestamp, seq }
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.timestamp, self.seq)
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((timestamp, seq)) = s.split_once(':') else {
+            bail!("invalid cursor '{s}'");
+        };
+        Ok(Cursor {
+            timestamp: timestamp
+                .parse()
+                .with_context(|| format!("invalid cursor '{s}'"))?,
+            seq: seq
+                .parse()
+                .with_context(|| format!("invalid cursor '{s}'"))?,
+        })
+    }
+}
+
+/// One page of a sorted result set
+pub struct Page<'a> {
+    pub mails: Vec<&'a Mail>,
+    pub total: usize,
+    pub next_cursor: Option<Cursor>,
+}
+
+/// Sorts the given mails by timestamp and returns at most `limit` (at least 1) of them,
+/// starting after `cursor` if one is given.
+pub fn paginate<'a>(
+    mut mails: Vec<&'a Mail>,
+    order: SortOrder,
+    cursor: Option<&Cursor>,
+    limit: usize,
+) -> Page<'a> {
+    let total = mails.len();
+    let limit = limit.max(1);
+    match order {
+        SortOrder::Asc => mails.sort_unstable_by_key(|m| m.sort_key()),
+        SortOrder::Desc => mails.sort_unstable_by_key(|m| std::cmp::Reverse(m.sort_key())),
+    }
+    let start = match cursor {
+        None => 0,
+        Some(c) => mails.partition_point(|m| match order {
+            SortOrder::Asc => m.sort_key() <= c.key(),
+            SortOrder::Desc => m.sort_key() >= c.key(),
+        }),
+    };
+    let end = total.min(start.saturating_add(limit));
+    let next_cursor = if end < total {
+        mails.get(end - 1).map(|m| Cursor::from(*m))
+    } else {
+        None
+    };
+    Page {
+        mails: mails.drain(start..end).collect(),
+        total,
+        next_cursor,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn mail(timestamp: i64, seq: u64) -> Mail {
+        Mail {
+            timestamp: Utc.timestamp_opt(timestamp, 0).single(),
+            seq,
+            ..Mail::for_test(&format!("ID{seq}"), "bob@example.com")
+        }
+    }
+
+    fn seqs(page: &Page) -> Vec<u64> {
+        page.mails.iter().map(|m| m.seq).collect()
+    }
+
+    #[test]
+    fn cursor_round_trip() {
+        let cursor = Cursor {
+            timestamp: -5,
+            seq: 42,
+        };
+        assert_eq!(cursor.to_string(), "-5:42");
+        assert_eq!(cursor.to_string().parse::<Cursor>().unwrap(), cursor);
+        assert!("42".parse::<Cursor>().is_err());
+        assert!("a:1".parse::<Cursor>().is_err());
+        assert!("1:-1".parse::<Cursor>().is_err());
+    }
+
+    #[test]
+    fn paginate_asc() {
+        let mails = [mail(30, 0), mail(10, 1), mail(20, 2), mail(10, 3)];
+        let page = paginate(mails.iter().collect(), SortOrder::Asc, None, 3);
+        assert_eq!(seqs(&page), [1, 3, 2]);
+        assert_eq!(page.total, 4);
+        let cursor = page.next_cursor.unwrap();
+        let page = paginate(mails.iter().collect(), SortOrder::Asc, Some(&cursor), 3);
+        assert_eq!(seqs(&page), [0]);
+        assert!(page.next_cursor.is_none());
+    }
+
+    #[test]
+    fn paginate_desc() {
+        let mails = [mail(30, 0), mail(10, 1), mail(20, 2), mail(10, 3)];
+        let page = paginate(mails.iter().collect(), SortOrder::Desc, None, 2);
+        assert_eq!(seqs(&page), [0, 2]);
+        let cursor = page.next_cursor.unwrap();
+        let page = paginate(mails.iter().collect(), SortOrder::Desc, Some(&cursor), 2);
+        assert_eq!(seqs(&page), [3, 1]);
+        assert!(page.next_cursor.is_none());
+    }
+
+    #[test]
+    fn paginate_without_timestamps_sorts_first() {
+        let mut undated = mail(0, 1);
+        undated.timestamp = None;
+        let mails = [mail(10, 0), undated];
+        let page = paginate(mails.iter().collect(), SortOrder::Asc, None, 0);
+        assert_eq!(seqs(&page), [1]);
+        assert_eq!(
+            page.next_cursor.unwrap().to_string(),
+            format!("{}:1", i64::MIN)
+        );
+    }
+
+    #[test]
+    fn regex_length_counts_characters() {
+        assert!(Matcher::new(MatchMode::Regex, &"é".repeat(MAX_REGEX_LEN)).is_ok());
+        assert!(Matcher::new(MatchMode::Regex, &"a".repeat(MAX_REGEX_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn budget_bounds_matched_mails() {
+        let budget = Budget::new(10, Duration::from_secs(60));
+        assert!(budget.check_matched(10).is_ok());
+        assert!(matches!(
+            budget.check_matched(11),
+            Err(TooBroad::Matched(10))
+        ));
+        assert!(budget.check_deadline(1024).is_ok());
+        assert!(Budget::unlimited().check_matched(usize::MAX).is_ok());
+    }
+
+    #[test]
+    fn budget_times_out() {
+        let budget = Budget::new(usize::MAX, Duration::ZERO);
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(budget.check_deadline(1).is_ok());
+        assert!(matches!(
+            budget.check_deadline(1024),
+            Err(TooBroad::Timeout(0))
+        ));
+    }
+}
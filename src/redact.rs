@@ -0,0 +1,176 @@
+use crate::mail::Mail;
+use hmac::{Hmac, Mac};
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use serde::Deserialize;
+use sha2::Sha256;
+
+/// Anything that looks like an address within a log line
+static ADDRESS: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"[^\s<>@,;:"'()\[\]]+@[^\s<>@,;:"'()\[\]]+"#).unwrap());
+
+/// How addresses are shown to identities with a redaction profile
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressRedaction {
+    #[default]
+    None,
+    /// Replace the local part, e.g. ***@x.com
+    Mask,
+    /// Replace the local part with a keyed HMAC of the address, so the same address can be correlated
+    Hash,
+}
+
+/// What is redacted from mails before they are returned, selected per token or client
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RedactionProfile {
+    #[serde(default)]
+    pub addresses: AddressRedaction,
+    #[serde(default)]
+    pub drop_subjects: bool,
+    /// Redact addresses within the raw log line, masked if addresses aren't redacted otherwise
+    #[serde(default)]
+    pub scrub_lines: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Redactor {
+    profile: RedactionProfile,
+    key: Vec<u8>,
+}
+
+impl Redactor {
+    pub fn new(profile: RedactionProfile, key: &str) -> Self {
+        Redactor {
+            profile,
+            key: key.as_bytes().to_vec(),
+        }
+    }
+
+    fn redact_address_as(&self, address: &str, mode: AddressRedaction) -> String {
+        let domain = address.rsplit_once('@').map_or("", |(_, domain)| domain);
+        match mode {
+            AddressRedaction::None => address.to_string(),
+            // The null sender of bounces is no personal data
+            _ if address.is_empty() => String::new(),
+            AddressRedaction::Mask => format!("***@{domain}"),
+            AddressRedaction::Hash => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(&self.key)
+                    .expect("HMAC can take a key of any size");
+                mac.update(address.to_lowercase().as_bytes());
+                let digest = mac.finalize().into_bytes();
+                format!("{}@{domain}", hex::encode(&digest[..8]))
+            }
+        }
+    }
+
+    /// Whether addresses are redacted in the address fields
+    pub fn redacts_addresses(&self) -> bool {
+        self.profile.addresses != AddressRedaction::None
+    }
+
+    /// Whether addresses are redacted anywhere, including the log lines
+    pub fn redacts_any_addresses(&self) -> bool {
+        self.redacts_addresses() || self.profile.scrub_lines
+    }
+
+    pub fn drops_subjects(&self) -> bool {
+        self.profile.drop_subjects
+    }
+
+    pub fn redact_address(&self, address: &str) -> String {
+        self.redact_address_as(address, self.profile.addresses)
+    }
+
+    pub fn redact(&self, mut mail: Mail) -> Mail {
+        mail.to = self.redact_address(&mail.to);
+        mail.from = mail.from.map(|from| self.redact_address(&from));
+        if self.profile.drop_subjects {
+            mail.subject = None;
+        }
+        if self.profile.scrub_lines {
+            let mode = match self.profile.addresses {
+                AddressRedaction::None => AddressRedaction::Mask,
+                mode => mode,
+            };
+            mail.line = mail.line.map(|line| {
+                ADDRESS
+                    .replace_all(&line, |c: &Captures| self.redact_address_as(&c[0], mode))
+                    .into_owned()
+            });
+        }
+        mail
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mail() -> Mail {
+        Mail {
+            from: Some(String::from("alice@sender.org")),
+            status: Some(String::from("sent")),
+            subject: Some(String::from("Invoice")),
+            line: Some(String::from(
+                "4ABCDEF123: to=<bob@example.com>, orig_to=<x@y.org> status=sent",
+            )),
+            ..Mail::for_test("4ABCDEF123", "bob@example.com")
+        }
+    }
+
+    fn redactor(addresses: AddressRedaction, drop_subjects: bool, scrub_lines: bool) -> Redactor {
+        let profile = RedactionProfile {
+            addresses,
+            drop_subjects,
+            scrub_lines,
+        };
+        Redactor::new(profile, "key")
+    }
+
+    #[test]
+    fn redact_masks_addresses() {
+        let mail = redactor(AddressRedaction::Mask, true, false).redact(mail());
+        assert_eq!(mail.to, "***@example.com");
+        assert_eq!(mail.from.as_deref(), Some("***@sender.org"));
+        assert_eq!(mail.subject, None);
+        assert!(mail.line.unwrap().contains("<bob@example.com>"));
+    }
+
+    #[test]
+    fn redact_hashes_addresses_consistently() {
+        let redactor = redactor(AddressRedaction::Hash, false, true);
+        let mail = redactor.redact(mail());
+        assert_ne!(mail.to, "bob@example.com");
+        assert!(mail.to.ends_with("@example.com"));
+        assert_eq!(mail.to, redactor.redact_address("BOB@example.com"));
+        let other = Redactor::new(RedactionProfile::default(), "other");
+        assert_ne!(
+            mail.to,
+            other.redact_address_as("bob@example.com", AddressRedaction::Hash)
+        );
+        assert_eq!(mail.subject.as_deref(), Some("Invoice"));
+        assert_eq!(
+            mail.line.unwrap(),
+            format!(
+                "4ABCDEF123: to=<{}>, orig_to=<{}> status=sent",
+                mail.to,
+                redactor.redact_address("x@y.org")
+            )
+        );
+    }
+
+    #[test]
+    fn redact_scrubs_lines_with_masks_by_default() {
+        let mut original = mail();
+        original.from = Some(String::new());
+        let mail = redactor(AddressRedaction::None, false, true).redact(original);
+        assert_eq!(mail.to, "bob@example.com");
+        assert_eq!(mail.from.as_deref(), Some(""));
+        assert_eq!(
+            mail.line.unwrap(),
+            "4ABCDEF123: to=<***@example.com>, orig_to=<***@y.org> status=sent"
+        );
+    }
+}
@@ -0,0 +1,219 @@
+use crate::check;
+use crate::cli::ConfigArgs;
+use crate::config::{read_config, Config};
+use crate::health::HEALTH;
+use crate::mail::{configured_files, load_files, tail_mail, tail_mail_log};
+use crate::supervisor::supervise;
+use crate::{audit, limit, suppress};
+use anyhow::Result;
+use log::{error, info, warn};
+use notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult, Debouncer};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::select;
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// A supervised task tailing a file
+struct Tailer {
+    file_path: PathBuf,
+    handle: JoinHandle<Result<String>>,
+}
+
+/// The tailers of the configured sources, by task name
+#[derive(Default)]
+struct Tailers(BTreeMap<&'static str, Tailer>);
+
+/// The file each tailer should tail, none if its tail isn't configured
+fn tail_files(config: &Config) -> [(&'static str, Option<PathBuf>); 2] {
+    let path =
+        |dir: &String, tail: &String| (!tail.is_empty()).then(|| [dir, tail].iter().collect());
+    [
+        ("tail_mail_log", path(&config.log.dir, &config.log.tail)),
+        ("tail_mail", path(&config.mail.dir, &config.mail.tail)),
+    ]
+}
+
+impl Tailers {
+    /// Starts, stops or restarts the tailers whose file changed
+    async fn update(&mut self, config: &Config) {
+        for (name, file_path) in tail_files(config) {
+            if self.0.get(name).map(|t| &t.file_path) == file_path.as_ref() {
+                continue;
+            }
+            if let Some(tailer) = self.0.remove(name) {
+                info!("Stopping {name} of {}", tailer.file_path.display());
+                tailer.handle.abort();
+                // Wait until it stopped, so its file isn't reported as dead after removing it
+                let _ = tailer.handle.await;
+                HEALTH.remove_tailer(&tailer.file_path);
+            }
+            let Some(file_path) = file_path else {
+                continue;
+            };
+            let task_path = file_path.clone();
+            let handle = match name {
+                "tail_mail_log" => {
+                    tokio::spawn(supervise(name, move || tail_mail_log(task_path.clone())))
+                }
+                _ => tokio::spawn(supervise(name, move || tail_mail(task_path.clone()))),
+            };
+            self.0.insert(name, Tailer { file_path, handle });
+        }
+    }
+}
+
+/// Applies a config that was read again, if it is valid. The DB is kept, files that were added
+/// are loaded into it and tailers follow their configured files. Mails of removed files stay.
+async fn reload(args: &ConfigArgs, tailers: &mut Tailers) {
+    let config = match read_config(args) {
+        Ok(config) => config,
+        Err(why) => {
+            error!("Keeping current config, reloading failed: {why:#}");
+            return;
+        }
+    };
+    let report = check::check(&config);
+    report.log();
+    if report.has_errors() {
+        error!("Keeping current config, run check-config for a full report");
+        return;
+    }
+    let old = Config::replace(config);
+    let new = Config::global();
+    let restart_required = [
+        ("listen", old.listen != new.listen),
+        ("tls", old.tls != new.tls),
+        ("cors_origins", old.cors_origins != new.cors_origins),
+    ];
+    for (name, changed) in restart_required {
+        if changed {
+            warn!("Config: {name} changed, this takes effect after a restart");
+        }
+    }
+    if let Err(why) = suppress::load() {
+        error!("Failed to reload suppression rules: {why:#}");
+    }
+    audit::reload();
+    limit::reload(&old.limits, &new.limits);
+    tailers.update(&new).await;
+
+    let (old_log_files, old_mail_files) = configured_files(&old);
+    let (log_files, mail_files) = configured_files(&new);
+    HEALTH.retain_files(&[log_files.as_slice(), &mail_files].concat());
+    let added = |files: &[PathBuf], old: &[PathBuf]| {
+        files
+            .iter()
+            .filter(|f| !old.contains(f))
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    let (log_files, mail_files) = (
+        added(&log_files, &old_log_files),
+        added(&mail_files, &old_mail_files),
+    );
+    if !log_files.is_empty() || !mail_files.is_empty() {
+        info!("Loading added files into DB...");
+        if let Err(why) = load_files(&log_files, &mail_files).await {
+            error!("Failed to load added files: {why:#}");
+        }
+    }
+    info!("Reloaded config from {}", args.source());
+}
+
+/// What a FileWatcher noticed
+pub enum Trigger {
+    Hangup,
+    Changed,
+}
+
+/// Notices SIGHUP and changes to a set of files. Their directories are watched instead of the files,
+/// because editors, config management and certificate renewals (e.g. certbot) replace them.
+pub struct FileWatcher {
+    /// What is watched, for log messages
+    what: &'static str,
+    file_names: BTreeSet<OsString>,
+    hangup: Signal,
+    rx: mpsc::UnboundedReceiver<DebounceEventResult>,
+    _debouncer: Debouncer<RecommendedWatcher>,
+}
+
+impl FileWatcher {
+    pub fn new<'a>(what: &'static str, files: impl IntoIterator<Item = &'a Path>) -> Result<Self> {
+        let hangup = signal(SignalKind::hangup())?;
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut debouncer = new_debouncer(
+            Duration::from_secs(2),
+            None,
+            move |res: DebounceEventResult| {
+                let _ = tx.send(res);
+            },
+        )?;
+        let mut file_names = BTreeSet::new();
+        let mut dirs = BTreeSet::new();
+        for file in files {
+            file_names.extend(file.file_name().map(OsString::from));
+            dirs.insert(
+                file.parent()
+                    .filter(|d| !d.as_os_str().is_empty())
+                    .unwrap_or(Path::new(".")),
+            );
+        }
+        for dir in dirs {
+            if let Err(why) = debouncer.watcher().watch(dir, RecursiveMode::NonRecursive) {
+                warn!(
+                    "Can't watch {} for {what} changes, reload with SIGHUP: {why}",
+                    dir.display()
+                );
+            }
+        }
+        Ok(FileWatcher {
+            what,
+            file_names,
+            hangup,
+            rx,
+            _debouncer: debouncer,
+        })
+    }
+
+    /// Waits until SIGHUP is received or any of the files changed
+    pub async fn changed(&mut self) -> Trigger {
+        loop {
+            select! {
+                _ = self.hangup.recv() => return Trigger::Hangup,
+                Some(res) = self.rx.recv() => match res {
+                    Ok(events) => {
+                        let changed = events.iter().any(|e| {
+                            e.path
+                                .file_name()
+                                .is_some_and(|f| self.file_names.contains(f))
+                        });
+                        if changed {
+                            return Trigger::Changed;
+                        }
+                    }
+                    Err(why) => warn!("Error while watching {}: {why:?}", self.what),
+                },
+            }
+        }
+    }
+}
+
+/// Starts the tailers and reloads the config on SIGHUP and whenever its file changes
+pub async fn watch_config(args: ConfigArgs) -> Result<String> {
+    let mut tailers = Tailers::default();
+    tailers.update(&Config::global()).await;
+    let file_path = args.file_path();
+    let mut watcher = FileWatcher::new("config file", file_path.as_deref())?;
+    loop {
+        match watcher.changed().await {
+            Trigger::Hangup => info!("SIGHUP received, reloading config"),
+            Trigger::Changed => info!("Config file changed, reloading"),
+        }
+        reload(&args, &mut tailers).await;
+    }
+}
@@ -0,0 +1,286 @@
+use crate::mail::Mail;
+use crate::redact::Redactor;
+use chrono::{DateTime, TimeZone, Utc};
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use thiserror::Error;
+use utoipa::ToSchema;
+
+/// Most buckets a single stats query may return per group
+pub const MAX_BUCKETS: i64 = 1000;
+
+/// What delivery statistics are grouped by
+#[derive(Debug, Clone, Copy, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StatsGroupBy {
+    /// Recipient domain
+    Domain,
+    /// Hostname of the relay, without address and port
+    Relay,
+    /// Delivery status, e.g. sent, deferred or bounced
+    Status,
+    /// First digit of the DSN, e.g. 5.x.x for permanent failures
+    DsnClass,
+    /// Envelope sender
+    Sender,
+}
+
+impl StatsGroupBy {
+    /// Senders are redacted for identities with a redaction profile, recipients are only grouped by domain
+    fn key(&self, mail: &Mail, redactor: Option<&Redactor>) -> String {
+        let unknown = || String::from("unknown");
+        match self {
+            StatsGroupBy::Domain => mail
+                .to
+                .rsplit_once('@')
+                .map_or_else(unknown, |(_, domain)| domain.to_lowercase()),
+            StatsGroupBy::Relay => mail.relay.as_deref().map_or_else(unknown, |relay| {
+                relay
+                    .split(['[', ':'])
+                    .next()
+                    .unwrap_or(relay)
+                    .to_lowercase()
+            }),
+            StatsGroupBy::Status => mail.status.clone().unwrap_or_else(unknown),
+            StatsGroupBy::DsnClass => mail
+                .dsn
+                .as_deref()
+                .and_then(|dsn| dsn.chars().next())
+                .map_or_else(unknown, |class| format!("{class}.x.x")),
+            StatsGroupBy::Sender => match mail.from.as_deref() {
+                None => unknown(),
+                Some("") => String::from("<>"),
+                Some(from) => redactor.map_or_else(
+                    || from.to_lowercase(),
+                    |r| r.redact_address(&from.to_lowercase()),
+                ),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StatsGroup {
+    pub total: usize,
+    pub series: Vec<Bucket>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Bucket {
+    pub start: DateTime<Utc>,
+    pub count: usize,
+}
+
+/// Why the parameters of a stats query don't make a window
+#[derive(Error, Debug)]
+pub enum InvalidWindow {
+    #[error("bucket has to be a positive amount of seconds")]
+    Bucket,
+    #[error("since has to be before until")]
+    Empty,
+    #[error("window spans more than {MAX_BUCKETS} buckets, increase the bucket size")]
+    TooManyBuckets,
+}
+
+impl InvalidWindow {
+    /// The query parameter to change
+    pub fn parameter(&self) -> &'static str {
+        match self {
+            InvalidWindow::Bucket | InvalidWindow::TooManyBuckets => "bucket",
+            InvalidWindow::Empty => "since",
+        }
+    }
+}
+
+/// Time window of a stats query, divided into buckets of equal length
+#[derive(Debug, Clone, Copy)]
+pub struct Window {
+    /// Start of the first bucket, at or before `since`
+    start: i64,
+    since: i64,
+    end: i64,
+    bucket_seconds: i64,
+    buckets: usize,
+}
+
+impl Window {
+    /// Aligns the start of the buckets to a multiple of the bucket length, so series of consecutive
+    /// queries line up, while the first bucket only counts from `since`.
+    /// Buckets longer than the window are shortened to it.
+    pub fn new(
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+        bucket_seconds: i64,
+    ) -> Result<Self, InvalidWindow> {
+        if bucket_seconds <= 0 {
+            return Err(InvalidWindow::Bucket);
+        }
+        if since >= until {
+            return Err(InvalidWindow::Empty);
+        }
+        let end = until.timestamp();
+        let bucket_seconds = bucket_seconds.min(end - since.timestamp());
+        let aligned = since.timestamp() - since.timestamp().rem_euclid(bucket_seconds);
+        // Not aligned if that would start before the earliest representable time
+        let start = match Utc.timestamp_opt(aligned, 0).single() {
+            Some(_) => aligned,
+            None => since.timestamp(),
+        };
+        let buckets = (end - start)
+            .checked_add(bucket_seconds - 1)
+            .map(|n| n / bucket_seconds)
+            .filter(|n| *n <= MAX_BUCKETS)
+            .ok_or(InvalidWindow::TooManyBuckets)?;
+        Ok(Window {
+            start,
+            since: since.timestamp(),
+            end,
+            bucket_seconds,
+            buckets: buckets as usize,
+        })
+    }
+
+    /// Length of the buckets, which is at most the length of the window
+    pub fn bucket_seconds(&self) -> i64 {
+        self.bucket_seconds
+    }
+
+    fn bucket(&self, timestamp: i64) -> Option<usize> {
+        if timestamp < self.since || timestamp >= self.end {
+            return None;
+        }
+        Some(((timestamp - self.start) / self.bucket_seconds) as usize)
+    }
+}
+
+/// Counts the messages within the window per group and per bucket. A message to a recipient
+/// is counted once, by its last delivery attempt within the window, so deferred and then sent
+/// mails only count as sent. Attempts without a timestamp can't be placed in the window and are skipped.
+pub fn aggregate<'a>(
+    mails: impl IntoIterator<Item = &'a Mail>,
+    group_by: StatsGroupBy,
+    window: Window,
+    redactor: Option<&Redactor>,
+) -> BTreeMap<String, StatsGroup> {
+    let mut last_attempts: FxHashMap<(&str, &str), (usize, &Mail)> = FxHashMap::default();
+    for mail in mails {
+        let Some(bucket) = mail.timestamp.and_then(|t| window.bucket(t.timestamp())) else {
+            continue;
+        };
+        last_attempts
+            .entry((&mail.id, &mail.to))
+            .and_modify(|last| {
+                if mail.sort_key() > last.1.sort_key() {
+                    *last = (bucket, mail);
+                }
+            })
+            .or_insert((bucket, mail));
+    }
+    let mut counts: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    for (bucket, mail) in last_attempts.into_values() {
+        counts
+            .entry(group_by.key(mail, redactor))
+            .or_insert_with(|| vec![0; window.buckets])[bucket] += 1;
+    }
+    counts
+        .into_iter()
+        .map(|(key, buckets)| {
+            let series = buckets
+                .iter()
+                .enumerate()
+                .map(|(i, count)| Bucket {
+                    start: Utc
+                        .timestamp_opt(window.start + i as i64 * window.bucket_seconds, 0)
+                        .unwrap(),
+                    count: *count,
+                })
+                .collect();
+            let group = StatsGroup {
+                total: buckets.iter().sum(),
+                series,
+            };
+            (key, group)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(timestamp: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(timestamp, 0).unwrap()
+    }
+
+    fn attempt(id: &str, to: &str, timestamp: i64, status: &str) -> Mail {
+        Mail {
+            timestamp: Some(at(timestamp)),
+            status: Some(status.into()),
+            ..Mail::for_test(id, to)
+        }
+    }
+
+    #[test]
+    fn aggregate_counts_last_attempts() {
+        let mails = [
+            attempt("A", "bob@example.com", 10, "deferred"),
+            attempt("A", "bob@example.com", 70, "sent"),
+            attempt("A", "eve@example.com", 20, "deferred"),
+            attempt("B", "bob@example.com", 30, "sent"),
+            attempt("C", "bob@example.com", 500, "bounced"),
+        ];
+        let window = Window::new(at(0), at(120), 60).unwrap();
+        let groups = aggregate(&mails, StatsGroupBy::Status, window, None);
+        let counts = |status: &str| -> Vec<usize> {
+            groups[status].series.iter().map(|b| b.count).collect()
+        };
+        assert_eq!(groups.keys().collect::<Vec<_>>(), ["deferred", "sent"]);
+        assert_eq!(counts("sent"), [1, 1]);
+        assert_eq!(counts("deferred"), [1, 0]);
+        assert_eq!(groups["sent"].total, 2);
+    }
+
+    #[test]
+    fn aggregate_skips_attempts_before_an_unaligned_since() {
+        let mails = [
+            attempt("A", "bob@example.com", 3601, "bounced"),
+            attempt("B", "bob@example.com", 5400, "bounced"),
+        ];
+        let window = Window::new(at(5400), at(9000), 3600).unwrap();
+        let groups = aggregate(&mails, StatsGroupBy::Status, window, None);
+        assert_eq!(groups["bounced"].total, 1);
+        assert_eq!(groups["bounced"].series[0].start, at(3600));
+    }
+
+    #[test]
+    fn window_aligns_buckets() {
+        let window = Window::new(at(3700), at(7300), 3600).unwrap();
+        assert_eq!((window.start, window.end, window.buckets), (3600, 7300, 2));
+        assert_eq!(window.bucket(3600), None);
+        assert_eq!(window.bucket(3699), None);
+        assert_eq!(window.bucket(3700), Some(0));
+        assert_eq!(window.bucket(7199), Some(0));
+        assert_eq!(window.bucket(7200), Some(1));
+        assert_eq!(window.bucket(7300), None);
+    }
+
+    #[test]
+    fn window_shortens_long_buckets() {
+        let window = Window::new(at(100), at(160), i64::MAX).unwrap();
+        assert_eq!(window.bucket_seconds(), 60);
+        assert_eq!((window.start, window.buckets), (60, 2));
+        let window = Window::new(DateTime::<Utc>::MIN_UTC, DateTime::<Utc>::MAX_UTC, i64::MAX);
+        assert_eq!(window.unwrap().buckets, 1);
+    }
+
+    #[test]
+    fn window_rejects_invalid_bounds() {
+        assert!(Window::new(at(0), at(60), 0).is_err());
+        assert!(Window::new(at(60), at(60), 1).is_err());
+        assert!(Window::new(at(0), at(MAX_BUCKETS), 1).is_ok());
+        assert!(Window::new(at(0), at(MAX_BUCKETS + 1), 1).is_err());
+        let window = Window::new(DateTime::<Utc>::MIN_UTC, at(0), i64::MAX).unwrap();
+        assert_eq!(window.start, DateTime::<Utc>::MIN_UTC.timestamp());
+    }
+}
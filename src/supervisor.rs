@@ -0,0 +1,43 @@
+use crate::health::HEALTH;
+use crate::metrics;
+use anyhow::Result;
+use log::{error, info};
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::{self, Instant};
+
+/// Delay before the first restart of a failed task, doubled on every consecutive failure
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+/// A task that ran at least this long before failing is restarted with the initial backoff again
+const HEALTHY_RUN: Duration = Duration::from_secs(60);
+
+/// Runs a task until it finishes successfully, restarting it with exponential backoff
+/// whenever it fails. Failures are exposed through the readiness report and metrics.
+pub async fn supervise<F, Fut>(name: &'static str, task: F) -> Result<String>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<String>>,
+{
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        let started = Instant::now();
+        match task().await {
+            Ok(res) => return Ok(res),
+            Err(why) => {
+                if started.elapsed() >= HEALTHY_RUN {
+                    backoff = INITIAL_BACKOFF;
+                }
+                error!(
+                    "Task {name} failed, restarting in {}s: {why:?}",
+                    backoff.as_secs()
+                );
+                HEALTH.task_failed(name, &why);
+                metrics::TASK_RESTARTS.with_label_values(&[name]).inc();
+                time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                info!("Restarting task {name}...");
+            }
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use crate::auth::in_domains;
+use crate::config::Config;
+use anyhow::{bail, Context, Result};
+use log::info;
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::str::FromStr;
+
+/// Addresses and domains that were erased and must not be ingested again
+static SUPPRESSIONS: Lazy<RwLock<Vec<Rule>>> = Lazy::new(|| RwLock::new(vec![]));
+
+/// A suppressed address, or a domain including its subdomains.
+/// Stored one per line in the suppression file, domains with a leading @.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rule {
+    Address(String),
+    Domain(String),
+}
+
+impl Rule {
+    pub fn matches(&self, address: &str) -> bool {
+        match self {
+            Rule::Address(a) => address.eq_ignore_ascii_case(a),
+            Rule::Domain(d) => in_domains(address, std::slice::from_ref(d)),
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rule::Address(a) => write!(f, "{a}"),
+            Rule::Domain(d) => write!(f, "@{d}"),
+        }
+    }
+}
+
+impl FromStr for Rule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim().to_lowercase();
+        match s.split_once('@') {
+            Some(("", domain)) if !domain.is_empty() && !domain.contains('@') => {
+                Ok(Rule::Domain(domain.to_string()))
+            }
+            Some((local, domain))
+                if !local.is_empty() && !domain.is_empty() && !domain.contains('@') =>
+            {
+                Ok(Rule::Address(s))
+            }
+            _ => bail!("'{s}' is neither an address nor a domain starting with @"),
+        }
+    }
+}
+
+/// Reads the rules of a suppression file, none if it doesn't exist yet
+pub fn read_rules(file_path: &str) -> Result<Vec<Rule>> {
+    let file = match File::open(file_path) {
+        Ok(f) => f,
+        Err(why) if why.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(why) => return Err(why).with_context(|| format!("while reading {file_path}")),
+    };
+    let mut rules = vec![];
+    for (n, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        rules.push(
+            line.parse()
+                .with_context(|| format!("in {file_path} line {}", n + 1))?,
+        );
+    }
+    Ok(rules)
+}
+
+/// Loads the suppression rules, before any mails are ingested
+pub fn load() -> Result<()> {
+    let file_path = &Config::global().suppression_file;
+    let rules = read_rules(file_path)?;
+    info!("Loaded {} suppression rules from {file_path}", rules.len());
+    *SUPPRESSIONS.write() = rules;
+    Ok(())
+}
+
+pub fn is_suppressed(address: &str) -> bool {
+    SUPPRESSIONS.read().iter().any(|rule| rule.matches(address))
+}
+
+/// Persists the rule before it takes effect, so it survives restarts
+pub fn suppress(rule: Rule) -> Result<()> {
+    let mut rules = SUPPRESSIONS.write();
+    if rules.contains(&rule) {
+        return Ok(());
+    }
+    let file_path = &Config::global().suppression_file;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file_path)
+        .with_context(|| format!("while opening {file_path}"))?;
+    writeln!(file, "{rule}")?;
+    file.sync_all()?;
+    rules.push(rule);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rule_parses_addresses_and_domains() {
+        assert_eq!(
+            " Bob@Example.com ".parse::<Rule>().unwrap(),
+            Rule::Address(String::from("bob@example.com"))
+        );
+        assert_eq!(
+            "@Example.com".parse::<Rule>().unwrap(),
+            Rule::Domain(String::from("example.com"))
+        );
+        for invalid in ["", "bob", "bob@", "@", "a@b@c", "@b@c"] {
+            assert!(invalid.parse::<Rule>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn rule_round_trips_through_the_file_format() {
+        for line in ["bob@example.com", "@example.com"] {
+            assert_eq!(line.parse::<Rule>().unwrap().to_string(), line);
+        }
+    }
+
+    #[test]
+    fn rule_matches() {
+        let address: Rule = "bob@example.com".parse().unwrap();
+        assert!(address.matches("BOB@example.com"));
+        assert!(!address.matches("bob@example.com.evil.org"));
+        let domain: Rule = "@example.com".parse().unwrap();
+        assert!(domain.matches("alice@mail.example.com"));
+        assert!(!domain.matches("alice@evil-example.com"));
+    }
+}
@@ -5,11 +5,12 @@ use std::path::PathBuf;
 use std::time::Duration;
 
 use anyhow::{bail, Context};
-use log::{debug, error, info, warn};
+use log::{debug, info, warn};
 use notify::{Config, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
 use tokio::sync::mpsc;
 
 use crate::mail::FileLines;
+use crate::metrics;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -80,6 +81,7 @@ impl FileTail {
         file.seek(SeekFrom::Start(self.pos))?;
         let reader = FileLines::from(file);
         self.pos = file_size;
+        metrics::set_tail_position(&self.file_path, self.pos);
         Ok(reader)
     }
 
@@ -94,6 +96,7 @@ impl FileTail {
             Config::default(),
         )?;
         watcher.watch(&file_path, RecursiveMode::NonRecursive)?;
+        metrics::set_tail_position(&file_path, pos);
         let file_tail = FileTail {
             pos,
             file_path,
@@ -119,9 +122,11 @@ impl FileTail {
                         warn!("{why:?}");
                         self.retry(5, Duration::from_secs(5)).await?;
                     }
-                    ParseEventError::Other(why) => warn!("Unknown event parser error occurred: {why:?}"),
+                    ParseEventError::Other(why) => {
+                        warn!("Unknown event parser error occurred: {why:?}")
+                    }
                     ParseEventError::UnhandledEvent(kind) => debug!("unhandled event: {:?}", kind),
-                }
+                },
             }
         }
         bail!("Ended task that is tailing file: {:?}.", self.file_path)
@@ -0,0 +1,184 @@
+use crate::config::ConfigTls;
+use crate::reload::{FileWatcher, Trigger};
+use anyhow::{anyhow, Context, Result};
+use axum::middleware::AddExtension;
+use axum::Extension;
+use axum_server::accept::Accept;
+use axum_server::tls_rustls::{RustlsAcceptor, RustlsConfig};
+use futures_util::future::BoxFuture;
+use log::{error, info};
+use rustls::server::{AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient};
+use rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+use rustls_pemfile::Item;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::server::TlsStream;
+use tower_layer::Layer;
+use x509_parser::extensions::GeneralName;
+
+/// Names of a verified client certificate, inserted into every request of its connection
+#[derive(Debug, Clone)]
+pub struct ClientCert {
+    pub common_name: Option<String>,
+    /// DNS, email and URI subject alternative names
+    pub alt_names: Vec<String>,
+}
+
+impl ClientCert {
+    fn parse(cert: &Certificate) -> Option<Self> {
+        let (_, cert) = x509_parser::parse_x509_certificate(&cert.0).ok()?;
+        let common_name = cert
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .map(String::from);
+        let alt_names = cert
+            .subject_alternative_name()
+            .ok()
+            .flatten()
+            .map(|san| {
+                san.value
+                    .general_names
+                    .iter()
+                    .filter_map(|name| match name {
+                        GeneralName::DNSName(n)
+                        | GeneralName::RFC822Name(n)
+                        | GeneralName::URI(n) => Some(n.to_string()),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Some(ClientCert {
+            common_name,
+            alt_names,
+        })
+    }
+
+    /// The common name followed by the subject alternative names
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.common_name
+            .iter()
+            .chain(self.alt_names.iter())
+            .map(String::as_str)
+    }
+}
+
+fn read_certs(path: &str) -> Result<Vec<Certificate>> {
+    let pem = std::fs::read(path).with_context(|| format!("while reading {path}"))?;
+    let certs = rustls_pemfile::certs(&mut pem.as_slice())?;
+    if certs.is_empty() {
+        return Err(anyhow!("no certificates found in {path}"));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn read_key(path: &str) -> Result<PrivateKey> {
+    let pem = std::fs::read(path).with_context(|| format!("while reading {path}"))?;
+    let mut reader = pem.as_slice();
+    while let Some(item) = rustls_pemfile::read_one(&mut reader)? {
+        if let Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key) = item {
+            return Ok(PrivateKey(key));
+        }
+    }
+    Err(anyhow!("no supported private key found in {path}"))
+}
+
+/// Builds the server config, verifying client certificates against the CA bundle if one is configured
+pub fn server_config(c: &ConfigTls) -> Result<ServerConfig> {
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match &c.client_ca {
+        None => builder.with_no_client_auth(),
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(client_ca)? {
+                roots.add(&cert)?;
+            }
+            let verifier = if c.optional_client_cert {
+                AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed()
+            } else {
+                AllowAnyAuthenticatedClient::new(roots).boxed()
+            };
+            builder.with_client_cert_verifier(verifier)
+        }
+    };
+    let mut config = builder.with_single_cert(read_certs(&c.cert)?, read_key(&c.key)?)?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+pub fn rustls_config(c: &ConfigTls) -> Result<RustlsConfig> {
+    Ok(RustlsConfig::from_config(Arc::new(server_config(c)?)))
+}
+
+fn tls_files(c: &ConfigTls) -> impl Iterator<Item = &Path> {
+    [Some(&c.cert), Some(&c.key), c.client_ca.as_ref()]
+        .into_iter()
+        .flatten()
+        .map(Path::new)
+}
+
+/// Swaps in newly loaded certificates, connections that are already established keep the old ones.
+/// The current certificates stay in use if the new ones can't be loaded.
+fn reload(c: &ConfigTls, rustls_config: &RustlsConfig) {
+    match server_config(c) {
+        Ok(config) => {
+            rustls_config.reload_from_config(Arc::new(config));
+            info!("Reloaded TLS certificates");
+        }
+        Err(why) => error!("Keeping current TLS certificates, reloading failed: {why:#}"),
+    }
+}
+
+/// Reloads the certificates on SIGHUP and whenever the cert, key or client CA files change
+pub async fn reload_certificates(c: ConfigTls, rustls_config: RustlsConfig) -> Result<String> {
+    let mut watcher = FileWatcher::new("TLS certificate files", tls_files(&c))?;
+    loop {
+        match watcher.changed().await {
+            Trigger::Hangup => info!("SIGHUP received, reloading TLS certificates"),
+            Trigger::Changed => info!("TLS certificate files changed, reloading"),
+        }
+        reload(&c, &rustls_config);
+    }
+}
+
+/// Accepts TLS connections and passes the client certificate on to the requests of each connection
+#[derive(Clone)]
+pub struct ClientCertAcceptor {
+    inner: RustlsAcceptor,
+}
+
+impl ClientCertAcceptor {
+    pub fn new(config: RustlsConfig) -> Self {
+        ClientCertAcceptor {
+            inner: RustlsAcceptor::new(config),
+        }
+    }
+}
+
+impl<I, S> Accept<I, S> for ClientCertAcceptor
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = TlsStream<I>;
+    type Service = AddExtension<S, Option<ClientCert>>;
+    type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let inner = self.inner.clone();
+        Box::pin(async move {
+            let (stream, service) = inner.accept(stream, service).await?;
+            let cert = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .and_then(ClientCert::parse);
+            Ok((stream, Extension(cert).layer(service)))
+        })
+    }
+}
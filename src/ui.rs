@@ -0,0 +1,10 @@
+use axum::response::Html;
+
+/// The search page, built into the binary so it is served wherever the API is
+static INDEX: &str = include_str!("../ui/index.html");
+
+/// Serves the web UI for searching mail. It calls the API from the browser, so the page
+/// itself is public and the API token is entered in the page.
+pub async fn index() -> Html<&'static str> {
+    Html(INDEX)
+}